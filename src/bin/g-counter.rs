@@ -1,20 +1,38 @@
-use fly_dist_sys::{kv::Kv, proto::MessageBody, Error, Node};
+use fly_dist_sys::{
+    crdt::GCounter,
+    gossip::{Gossip, GOSSIP},
+    proto::MessageBody,
+    Error, Node,
+};
+
+#[derive(Clone, Default)]
+struct State {
+    counter: Gossip<GCounter>,
+}
 
 #[tokio::main]
 async fn main() {
-    Node::new()
-        .serve(|node, req| async move {
-            let kv = Kv::new_seq_kv(&node);
+    let node = Node::<State>::default();
+    node.state().counter.spawn(node.clone());
 
-            match req.ty() {
-                "add" => {
-                    let delta = req.body.extra.get("delta").unwrap().as_u64().unwrap() as u64;
+    node.serve(|node, req| async move {
+        match req.ty() {
+            "add" => {
+                let delta = req.body.extra.get("delta").unwrap().as_u64().unwrap();
+                let node_id = node.id().await.clone();
+                node.state()
+                    .counter
+                    .update(|counter| counter.increment(&node_id, delta));
 
-                    Ok(MessageBody::new("add_ok"))
-                }
-                "read" => Ok(MessageBody::new("read_ok").with_field("value", 0)),
-                _ => Err(Error::not_supported()),
+                Ok(MessageBody::new("add_ok"))
+            }
+            "read" => {
+                let value = node.state().counter.value();
+                Ok(MessageBody::new("read_ok").with_field("value", value))
             }
-        })
-        .await;
+            GOSSIP => node.state().counter.handle(&req),
+            _ => Err(Error::not_supported()),
+        }
+    })
+    .await;
 }
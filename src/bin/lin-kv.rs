@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use fly_dist_sys::{
+    proto::MessageBody,
+    raft::{Raft, RaftConfig, StateMachine, APPEND_ENTRIES, REQUEST_VOTE},
+    Error, Node,
+};
+use serde_json::Value;
+
+/// Key-value store replicated by Raft. Keys are stored by their JSON encoding, since
+/// Maelstrom may use integers as keys.
+#[derive(Debug, Default)]
+struct KvStore {
+    data: HashMap<String, Value>,
+}
+
+impl StateMachine for KvStore {
+    fn apply(&mut self, req: &MessageBody) -> Result<MessageBody, Error> {
+        let field = |name: &str| {
+            req.extra
+                .get(name)
+                .cloned()
+                .ok_or_else(Error::malformed_request)
+        };
+        let key = field("key")?.to_string();
+
+        match req.ty.as_str() {
+            "read" => {
+                let value = self.data.get(&key).ok_or_else(Error::key_does_not_exist)?;
+                Ok(MessageBody::new("read_ok").with_field("value", value))
+            }
+            "write" => {
+                self.data.insert(key, field("value")?);
+                Ok(MessageBody::new("write_ok"))
+            }
+            "cas" => {
+                let current = self
+                    .data
+                    .get_mut(&key)
+                    .ok_or_else(Error::key_does_not_exist)?;
+                if *current != field("from")? {
+                    return Err(Error::precondition_failed());
+                }
+                *current = field("to")?;
+                Ok(MessageBody::new("cas_ok"))
+            }
+            _ => Err(Error::not_supported()),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct State {
+    raft: Raft<KvStore>,
+}
+
+#[tokio::main]
+async fn main() {
+    let node = Node::with_state(State {
+        raft: Raft::new(KvStore::default(), RaftConfig::default()),
+    });
+    node.state().raft.spawn(node.clone());
+
+    node.serve(|node, req| async move {
+        // Check the type before proposing, so requests the store can't apply never reach
+        // the log.
+        match req.ty() {
+            "read" | "write" | "cas" | REQUEST_VOTE | APPEND_ENTRIES => {}
+            _ => return Err(Error::not_supported()),
+        }
+        let raft = node.state().raft.clone();
+        raft.handle(&node, req).await
+    })
+    .await;
+}
@@ -0,0 +1,149 @@
+//! Render recorded message traces as a Mermaid sequence diagram.
+//!
+//! Usage: `viz [--node <id>]... <trace.jsonl>...`
+//!
+//! Traces come from running nodes with `FLY_DIST_SYS_TRACE` set. With `--node`, only
+//! messages sent or received by the given nodes are drawn.
+
+use std::collections::HashMap;
+
+use fly_dist_sys::{
+    proto::Message,
+    record::{read_trace, Direction, Record},
+};
+
+fn main() {
+    let mut nodes = Vec::new();
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--node" => match args.next() {
+                Some(node) => nodes.push(node),
+                None => usage(),
+            },
+            "-h" | "--help" => usage(),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        usage();
+    }
+
+    let mut records = Vec::new();
+    for path in &paths {
+        match read_trace(path) {
+            Ok(trace) => records.extend(trace),
+            Err(err) => {
+                eprintln!("{path}: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let msgs: Vec<Message> = dedup(records)
+        .into_iter()
+        .filter(|msg| nodes.is_empty() || nodes.contains(&msg.src) || nodes.contains(&msg.dst))
+        .collect();
+    print!("{}", render(&msgs));
+}
+
+fn usage() -> ! {
+    eprintln!("usage: viz [--node <id>]... <trace.jsonl>...");
+    std::process::exit(2);
+}
+
+/// Put records in the order they happened and drop the receiver's copy of any message whose
+/// sender also recorded it.
+fn dedup(mut records: Vec<Record>) -> Vec<Message> {
+    records.sort_by_key(|record| record.wall);
+
+    let mut sent: HashMap<String, usize> = HashMap::new();
+    for record in &records {
+        if record.direction == Direction::Out {
+            *sent.entry(key(&record.msg)).or_default() += 1;
+        }
+    }
+
+    records
+        .into_iter()
+        .filter(|record| {
+            if record.direction == Direction::Out {
+                return true;
+            }
+            match sent.get_mut(&key(&record.msg)) {
+                Some(n) if *n > 0 => {
+                    *n -= 1;
+                    false
+                }
+                _ => true,
+            }
+        })
+        .map(|record| record.msg)
+        .collect()
+}
+
+fn key(msg: &Message) -> String {
+    serde_json::to_string(msg).unwrap()
+}
+
+fn render(msgs: &[Message]) -> String {
+    let mut participants: Vec<&str> = Vec::new();
+    for msg in msgs {
+        for id in [msg.src.as_str(), msg.dst.as_str()] {
+            if !participants.contains(&id) {
+                participants.push(id);
+            }
+        }
+    }
+    // Clients first, then nodes, then services, keeping the order of appearance otherwise.
+    participants.sort_by_key(|id| match id.chars().next() {
+        Some('c') if id[1..].chars().all(|c| c.is_ascii_digit()) => 0,
+        Some('n') if id[1..].chars().all(|c| c.is_ascii_digit()) => 1,
+        _ => 2,
+    });
+
+    let mut out = String::from("sequenceDiagram\n");
+    for id in &participants {
+        out.push_str(&format!("    participant {} as {id}\n", alias(id)));
+    }
+
+    // Requests are labelled `src#msg_id` and replies point back at the same label, since
+    // message ids are only unique per sender.
+    for msg in msgs {
+        let (src, dst) = (alias(&msg.src), alias(&msg.dst));
+        let ty = label(msg.ty());
+        let line = match (msg.body.in_reply_to, msg.ty()) {
+            (0, _) if msg.body.msg_id == 0 => format!("{src}-){dst}: {ty}"),
+            (0, _) => format!("{src}->>{dst}: {ty} {}#{}", msg.src, msg.body.msg_id),
+            (id, "error") => format!("{src}--x{dst}: {} ↩ {}#{id}", error(msg), msg.dst),
+            (id, _) => format!("{src}-->>{dst}: {ty} ↩ {}#{id}", msg.dst),
+        };
+        out.push_str("    ");
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Mermaid participant ids may not contain `-`, which would read as an arrow.
+fn alias(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Escape characters Mermaid treats specially in message text.
+fn label(text: &str) -> String {
+    text.replace(';', "#59;").replace(':', "#58;")
+}
+
+fn error(msg: &Message) -> String {
+    let code = msg.body.extra.get("code").and_then(|code| code.as_u64());
+    let text = msg.body.extra.get("text").and_then(|text| text.as_str());
+    match (code, text) {
+        (Some(code), Some(text)) => label(&format!("error {code} {text}")),
+        (Some(code), None) => format!("error {code}"),
+        _ => "error".to_string(),
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::proto::MessageBody;
+
+/// A logical clock that can be stamped into outgoing messages and merged from incoming ones.
+///
+/// Install one on a node with [`Node::with_clock`](crate::Node::with_clock) to have every
+/// message sent by the node carry a timestamp under [`Clock::FIELD`], and every timestamp
+/// received in `serve` merged back into the clock.
+pub trait Clock: Send + Sync + 'static {
+    type Timestamp: Serialize + DeserializeOwned;
+
+    /// Field of `MessageBody.extra` the timestamp is stored under.
+    const FIELD: &'static str;
+
+    /// Advance the clock for an outgoing message and return its timestamp.
+    fn send(&self, node_id: &str) -> Self::Timestamp;
+
+    /// Merge the timestamp of an incoming message into the clock.
+    fn receive(&self, node_id: &str, ts: Self::Timestamp);
+}
+
+impl<C: Clock> Clock for Arc<C> {
+    type Timestamp = C::Timestamp;
+
+    const FIELD: &'static str = C::FIELD;
+
+    fn send(&self, node_id: &str) -> Self::Timestamp {
+        C::send(self, node_id)
+    }
+
+    fn receive(&self, node_id: &str, ts: Self::Timestamp) {
+        C::receive(self, node_id, ts)
+    }
+}
+
+/// Object safe wrapper around [`Clock`] so a node can hold any clock.
+pub(crate) trait DynClock: Send + Sync {
+    fn stamp(&self, node_id: &str, body: &mut MessageBody);
+    fn merge(&self, node_id: &str, body: &MessageBody);
+}
+
+impl<C: Clock> DynClock for C {
+    fn stamp(&self, node_id: &str, body: &mut MessageBody) {
+        let ts = self.send(node_id);
+        body.extra.insert(
+            C::FIELD.to_string(),
+            serde_json::to_value(ts).expect("Failed to serialize timestamp"),
+        );
+    }
+
+    fn merge(&self, node_id: &str, body: &MessageBody) {
+        let Some(ts) = body.extra.get(C::FIELD) else {
+            return;
+        };
+        match serde_json::from_value(ts.clone()) {
+            Ok(ts) => self.receive(node_id, ts),
+            Err(err) => tracing::warn!(?err, field = C::FIELD, "Invalid timestamp"),
+        }
+    }
+}
+
+/// Lamport clock, a single counter that respects causality.
+#[derive(Debug, Default)]
+pub struct LamportClock {
+    time: AtomicU64,
+}
+
+impl LamportClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current time without advancing the clock.
+    pub fn now(&self) -> u64 {
+        self.time.load(atomic::Ordering::SeqCst)
+    }
+
+    /// Advance the clock for a local event.
+    pub fn tick(&self) -> u64 {
+        self.time.fetch_add(1, atomic::Ordering::SeqCst) + 1
+    }
+
+    /// Merge a remote time, advancing past it.
+    pub fn observe(&self, ts: u64) -> u64 {
+        let prev = self
+            .time
+            .fetch_update(atomic::Ordering::SeqCst, atomic::Ordering::SeqCst, |t| {
+                Some(t.max(ts) + 1)
+            })
+            .unwrap();
+        prev.max(ts) + 1
+    }
+}
+
+impl Clock for LamportClock {
+    type Timestamp = u64;
+
+    const FIELD: &'static str = "lamport";
+
+    fn send(&self, _node_id: &str) -> u64 {
+        self.tick()
+    }
+
+    fn receive(&self, _node_id: &str, ts: u64) {
+        self.observe(ts);
+    }
+}
+
+/// A vector timestamp, mapping node ids to the number of events seen from that node.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VectorTimestamp(pub BTreeMap<String, u64>);
+
+impl VectorTimestamp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of events seen from a node.
+    pub fn get(&self, node_id: &str) -> u64 {
+        self.0.get(node_id).copied().unwrap_or_default()
+    }
+
+    /// Record a local event on a node.
+    pub fn increment(&mut self, node_id: &str) {
+        *self.0.entry(node_id.to_string()).or_default() += 1;
+    }
+
+    /// Take the element-wise maximum with another timestamp.
+    pub fn merge(&mut self, other: &Self) {
+        for (node_id, &n) in &other.0 {
+            let entry = self.0.entry(node_id.clone()).or_default();
+            *entry = (*entry).max(n);
+        }
+    }
+
+    /// Returns `true` if `self` causally precedes `other`.
+    pub fn happened_before(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Less)
+    }
+
+    /// Returns `true` if neither timestamp causally precedes the other.
+    pub fn concurrent(&self, other: &Self) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+}
+
+impl PartialEq for VectorTimestamp {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl Eq for VectorTimestamp {}
+
+impl PartialOrd for VectorTimestamp {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let mut ord = Ordering::Equal;
+        for node_id in self.0.keys().chain(other.0.keys()) {
+            let cmp = self.get(node_id).cmp(&other.get(node_id));
+            match (ord, cmp) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, cmp) => ord = cmp,
+                (ord, cmp) if ord != cmp => return None,
+                _ => {}
+            }
+        }
+        Some(ord)
+    }
+}
+
+/// Vector clock, tracking the causal history of a node.
+#[derive(Debug, Default)]
+pub struct VectorClock {
+    time: Mutex<VectorTimestamp>,
+}
+
+impl VectorClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current time without advancing the clock.
+    pub fn now(&self) -> VectorTimestamp {
+        self.time.lock().unwrap().clone()
+    }
+
+    /// Advance the clock for a local event on `node_id`.
+    pub fn tick(&self, node_id: &str) -> VectorTimestamp {
+        let mut time = self.time.lock().unwrap();
+        time.increment(node_id);
+        time.clone()
+    }
+
+    /// Merge a remote timestamp and record the receive event on `node_id`.
+    pub fn observe(&self, node_id: &str, ts: &VectorTimestamp) -> VectorTimestamp {
+        let mut time = self.time.lock().unwrap();
+        time.merge(ts);
+        time.increment(node_id);
+        time.clone()
+    }
+}
+
+impl Clock for VectorClock {
+    type Timestamp = VectorTimestamp;
+
+    const FIELD: &'static str = "vclock";
+
+    fn send(&self, node_id: &str) -> VectorTimestamp {
+        self.tick(node_id)
+    }
+
+    fn receive(&self, node_id: &str, ts: VectorTimestamp) {
+        self.observe(node_id, &ts);
+    }
+}
+
+/// A hybrid logical timestamp: physical milliseconds plus a logical counter to order events
+/// within the same millisecond.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct HybridTimestamp {
+    pub wall: u64,
+    pub logical: u32,
+}
+
+/// Hybrid logical clock, which stays close to wall-clock time while respecting causality.
+#[derive(Debug, Default)]
+pub struct HybridClock {
+    time: Mutex<HybridTimestamp>,
+}
+
+impl HybridClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current time without advancing the clock.
+    pub fn now(&self) -> HybridTimestamp {
+        *self.time.lock().unwrap()
+    }
+
+    /// Advance the clock for a local event.
+    pub fn tick(&self) -> HybridTimestamp {
+        let physical = physical_now();
+        let mut time = self.time.lock().unwrap();
+        if physical > time.wall {
+            *time = HybridTimestamp {
+                wall: physical,
+                logical: 0,
+            };
+        } else {
+            time.logical += 1;
+        }
+        *time
+    }
+
+    /// Merge a remote timestamp.
+    pub fn observe(&self, ts: HybridTimestamp) -> HybridTimestamp {
+        let physical = physical_now();
+        let mut time = self.time.lock().unwrap();
+        let wall = physical.max(time.wall).max(ts.wall);
+        let logical = if wall == time.wall && wall == ts.wall {
+            time.logical.max(ts.logical) + 1
+        } else if wall == time.wall {
+            time.logical + 1
+        } else if wall == ts.wall {
+            ts.logical + 1
+        } else {
+            0
+        };
+        *time = HybridTimestamp { wall, logical };
+        *time
+    }
+}
+
+impl Clock for HybridClock {
+    type Timestamp = HybridTimestamp;
+
+    const FIELD: &'static str = "hlc";
+
+    fn send(&self, _node_id: &str) -> HybridTimestamp {
+        self.tick()
+    }
+
+    fn receive(&self, _node_id: &str, ts: HybridTimestamp) {
+        self.observe(ts);
+    }
+}
+
+fn physical_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Read the timestamp a clock stamped into a message, if any.
+pub fn timestamp<C: Clock>(body: &MessageBody) -> Option<C::Timestamp> {
+    body.extra
+        .get(C::FIELD)
+        .cloned()
+        .and_then(|ts: Value| serde_json::from_value(ts).ok())
+}
@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// A state-based conflict-free replicated data type.
+///
+/// Replicas are keyed by the `node_id` from [`NodeMetadata`](crate::NodeMetadata). Any two
+/// replicas converge to the same state once they have merged each other's updates, regardless
+/// of order or duplication.
+pub trait Crdt: Clone + Default + Serialize + DeserializeOwned + Send + Sync + 'static {
+    type Value;
+
+    /// Merge another replica's state into this one.
+    fn merge(&mut self, other: &Self);
+
+    /// The value observed by readers.
+    fn value(&self) -> Self::Value;
+
+    /// The part of this state not already contained in `since`, or `None` if `since` has
+    /// seen everything. Merging the delta into `since` gives the same result as merging the
+    /// full state.
+    fn delta(&self, since: &Self) -> Option<Self>;
+}
+
+/// Grow-only counter.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter {
+    counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `n` to the count for a node.
+    pub fn increment(&mut self, node_id: &str, n: u64) {
+        *self.counts.entry(node_id.to_string()).or_default() += n;
+    }
+
+    /// The count contributed by a single node.
+    pub fn get(&self, node_id: &str) -> u64 {
+        self.counts.get(node_id).copied().unwrap_or_default()
+    }
+}
+
+impl Crdt for GCounter {
+    type Value = u64;
+
+    fn merge(&mut self, other: &Self) {
+        for (node_id, &n) in &other.counts {
+            let count = self.counts.entry(node_id.clone()).or_default();
+            *count = (*count).max(n);
+        }
+    }
+
+    fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    fn delta(&self, since: &Self) -> Option<Self> {
+        let counts: BTreeMap<_, _> = self
+            .counts
+            .iter()
+            .filter(|(node_id, &n)| n > since.get(node_id))
+            .map(|(node_id, &n)| (node_id.clone(), n))
+            .collect();
+        (!counts.is_empty()).then_some(Self { counts })
+    }
+}
+
+/// Counter supporting both increments and decrements.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    p: GCounter,
+    n: GCounter,
+}
+
+impl PNCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `n` on behalf of a node.
+    pub fn increment(&mut self, node_id: &str, n: u64) {
+        self.p.increment(node_id, n);
+    }
+
+    /// Subtract `n` on behalf of a node.
+    pub fn decrement(&mut self, node_id: &str, n: u64) {
+        self.n.increment(node_id, n);
+    }
+}
+
+impl Crdt for PNCounter {
+    type Value = i64;
+
+    fn merge(&mut self, other: &Self) {
+        self.p.merge(&other.p);
+        self.n.merge(&other.n);
+    }
+
+    fn value(&self) -> i64 {
+        self.p.value() as i64 - self.n.value() as i64
+    }
+
+    fn delta(&self, since: &Self) -> Option<Self> {
+        match (self.p.delta(&since.p), self.n.delta(&since.n)) {
+            (None, None) => None,
+            (p, n) => Some(Self {
+                p: p.unwrap_or_default(),
+                n: n.unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+/// Grow-only set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GSet<T: Ord> {
+    elements: BTreeSet<T>,
+}
+
+impl<T: Ord> Default for GSet<T> {
+    fn default() -> Self {
+        Self {
+            elements: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord> GSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an element, returning `true` if it was not already present.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.elements.insert(value)
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.elements.contains(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements.iter()
+    }
+}
+
+impl<T> Crdt for GSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    type Value = BTreeSet<T>;
+
+    fn merge(&mut self, other: &Self) {
+        self.elements.extend(other.elements.iter().cloned());
+    }
+
+    fn value(&self) -> BTreeSet<T> {
+        self.elements.clone()
+    }
+
+    fn delta(&self, since: &Self) -> Option<Self> {
+        let elements: BTreeSet<_> = self.elements.difference(&since.elements).cloned().collect();
+        (!elements.is_empty()).then_some(Self { elements })
+    }
+}
+
+/// A unique tag for a single insertion into an [`ORSet`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Dot {
+    pub node_id: String,
+    pub counter: u64,
+}
+
+/// Observed-remove set. An element is present if it has an insertion that has not been
+/// observed by a remove, so concurrent inserts win over removes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ORSet<T: Ord> {
+    entries: BTreeSet<(T, Dot)>,
+    tombstones: BTreeSet<Dot>,
+    counters: BTreeMap<String, u64>,
+}
+
+impl<T: Ord> Default for ORSet<T> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeSet::new(),
+            tombstones: BTreeSet::new(),
+            counters: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> ORSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an element on behalf of a node.
+    pub fn insert(&mut self, node_id: &str, value: T) {
+        let counter = self.counters.entry(node_id.to_string()).or_default();
+        *counter += 1;
+        let dot = Dot {
+            node_id: node_id.to_string(),
+            counter: *counter,
+        };
+        self.entries.insert((value, dot));
+    }
+
+    /// Remove every insertion of an element observed by this replica.
+    pub fn remove(&mut self, value: &T) {
+        let removed: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(v, _)| v == value)
+            .cloned()
+            .collect();
+        for entry in removed {
+            self.entries.remove(&entry);
+            self.tombstones.insert(entry.1);
+        }
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.entries.iter().any(|(v, _)| v == value)
+    }
+}
+
+impl<T> Crdt for ORSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    type Value = BTreeSet<T>;
+
+    fn merge(&mut self, other: &Self) {
+        self.tombstones.extend(other.tombstones.iter().cloned());
+        self.entries.extend(other.entries.iter().cloned());
+        let tombstones = &self.tombstones;
+        self.entries.retain(|(_, dot)| !tombstones.contains(dot));
+        for (node_id, &n) in &other.counters {
+            let counter = self.counters.entry(node_id.clone()).or_default();
+            *counter = (*counter).max(n);
+        }
+    }
+
+    fn value(&self) -> BTreeSet<T> {
+        self.entries.iter().map(|(v, _)| v.clone()).collect()
+    }
+
+    fn delta(&self, since: &Self) -> Option<Self> {
+        let entries: BTreeSet<_> = self
+            .entries
+            .iter()
+            .filter(|entry| !since.entries.contains(entry) && !since.tombstones.contains(&entry.1))
+            .cloned()
+            .collect();
+        let tombstones: BTreeSet<_> = self
+            .tombstones
+            .difference(&since.tombstones)
+            .cloned()
+            .collect();
+        let counters: BTreeMap<_, _> = self
+            .counters
+            .iter()
+            .filter(|(node_id, &n)| n > since.counters.get(*node_id).copied().unwrap_or_default())
+            .map(|(node_id, &n)| (node_id.clone(), n))
+            .collect();
+
+        if entries.is_empty() && tombstones.is_empty() && counters.is_empty() {
+            None
+        } else {
+            Some(Self {
+                entries,
+                tombstones,
+                counters,
+            })
+        }
+    }
+}
+
+/// Last-writer-wins register. Writes are ordered by timestamp, with ties broken by node id.
+///
+/// Timestamps can be any ordered type, such as the `u64` from a
+/// [`LamportClock`](crate::clock::LamportClock) or a
+/// [`HybridTimestamp`](crate::clock::HybridTimestamp), as long as every replica uses the same
+/// one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LWWRegister<T, Ts = u64> {
+    value: Option<T>,
+    timestamp: Ts,
+    node_id: String,
+}
+
+impl<T, Ts: Default> Default for LWWRegister<T, Ts> {
+    fn default() -> Self {
+        Self {
+            value: None,
+            timestamp: Ts::default(),
+            node_id: String::new(),
+        }
+    }
+}
+
+impl<T, Ts: Ord + Default> LWWRegister<T, Ts> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write a value on behalf of a node. The write is ignored if the register already holds
+    /// a later one.
+    ///
+    /// Timestamps should come from a clock that respects causality.
+    pub fn set(&mut self, node_id: &str, timestamp: Ts, value: T) {
+        if (&timestamp, node_id) > (&self.timestamp, self.node_id.as_str()) {
+            self.value = Some(value);
+            self.timestamp = timestamp;
+            self.node_id = node_id.to_string();
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    /// Timestamp of the current value.
+    pub fn timestamp(&self) -> &Ts {
+        &self.timestamp
+    }
+
+    fn newer_than(&self, other: &Self) -> bool {
+        (&self.timestamp, &self.node_id) > (&other.timestamp, &other.node_id)
+    }
+}
+
+impl<T, Ts> Crdt for LWWRegister<T, Ts>
+where
+    T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+    Ts: Ord + Clone + Default + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    type Value = Option<T>;
+
+    fn merge(&mut self, other: &Self) {
+        if other.newer_than(self) {
+            *self = other.clone();
+        }
+    }
+
+    fn value(&self) -> Option<T> {
+        self.value.clone()
+    }
+
+    fn delta(&self, since: &Self) -> Option<Self> {
+        self.newer_than(since).then(|| self.clone())
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+
+use crate::{
+    kv::{decode, encode, Kv},
+    Error, Node,
+};
+
+#[derive(Debug, Clone)]
+pub struct ElectionConfig {
+    key: String,
+    lease_duration: Duration,
+    renew_interval: Duration,
+}
+
+impl Default for ElectionConfig {
+    fn default() -> Self {
+        Self {
+            key: "leader".to_string(),
+            lease_duration: Duration::from_secs(1),
+            renew_interval: Duration::from_millis(200),
+        }
+    }
+}
+
+impl ElectionConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Key holding the lease. Separate elections need separate keys.
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = key.into();
+        self
+    }
+
+    /// How long a lease lasts without being renewed.
+    pub fn with_lease_duration(mut self, duration: Duration) -> Self {
+        self.lease_duration = duration;
+        self
+    }
+
+    /// Time between renewals by the leader and lease checks by everyone else. Should be a
+    /// small fraction of the lease duration.
+    pub fn with_renew_interval(mut self, interval: Duration) -> Self {
+        self.renew_interval = interval;
+        self
+    }
+}
+
+/// The value stored under the lease key. Every renewal bumps `seq`, so other nodes can tell
+/// a live lease from an abandoned one without comparing clocks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Lease {
+    leader: String,
+    term: u64,
+    seq: u64,
+}
+
+#[derive(Debug, Default)]
+struct ElectionState {
+    /// End of our own lease, counted from when the last renewal was sent.
+    leading_until: Option<Instant>,
+    term: u64,
+    /// The lease value last read and when it was first seen.
+    observed: Option<(Value, Instant)>,
+}
+
+struct ElectionInner {
+    state: Mutex<ElectionState>,
+    leader: watch::Sender<Option<String>>,
+    config: ElectionConfig,
+}
+
+/// Lease-based leader election over a linearizable key-value store.
+///
+/// The leader holds a lease under one key and renews it with compare-and-swap. Other nodes
+/// take over once they have seen the same lease value for a whole lease duration on their
+/// own clock. A leader only counts itself as leader for a lease duration after it sent its
+/// last successful renewal, which ends before anyone else can take over, so at most one node
+/// believes it is leader at a time as long as clocks run at roughly the same rate.
+///
+/// Call [`spawn`](Self::spawn) once to start campaigning.
+pub struct LeaderElection<S> {
+    kv: Kv<S>,
+    inner: Arc<ElectionInner>,
+}
+
+impl<S> Clone for LeaderElection<S> {
+    fn clone(&self) -> Self {
+        Self {
+            kv: self.kv.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S> LeaderElection<S> {
+    /// Elect a leader through `kv`, which must be [`Kv::new_lin_kv`].
+    pub fn new(kv: Kv<S>, config: ElectionConfig) -> Self {
+        Self {
+            kv,
+            inner: Arc::new(ElectionInner {
+                state: Mutex::new(ElectionState::default()),
+                leader: watch::channel(None).0,
+                config,
+            }),
+        }
+    }
+
+    /// Whether this node holds an unexpired lease.
+    pub fn is_leader(&self) -> bool {
+        let state = self.inner.state.lock().unwrap();
+        state
+            .leading_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    /// The term of this node's lease while it is leader. Terms increase with every change
+    /// of leader, so they can fence off writes from a deposed one.
+    pub fn term(&self) -> Option<u64> {
+        self.is_leader()
+            .then(|| self.inner.state.lock().unwrap().term)
+    }
+
+    /// The node last seen holding the lease, which may since have lost it.
+    pub fn current_leader(&self) -> Option<String> {
+        self.inner.leader.borrow().clone()
+    }
+
+    /// Watch the current leader as it changes.
+    pub fn subscribe(&self) -> watch::Receiver<Option<String>> {
+        self.inner.leader.subscribe()
+    }
+}
+
+impl<S> LeaderElection<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Start campaigning for, and renewing, the lease once the node is initialized.
+    pub fn spawn(&self, node: Node<S>) -> JoinHandle<()> {
+        let election = self.clone();
+        tokio::spawn(async move {
+            let node_id = node.wait_for_init().await.node_id;
+
+            let mut interval = tokio::time::interval(election.inner.config.renew_interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(err) = election.tick(&node_id).await {
+                    tracing::debug!(%err, "Lease check failed");
+                }
+                election.publish(&node_id);
+            }
+        })
+    }
+
+    /// Renew our lease, or take over one that has not been renewed for a whole lease
+    /// duration.
+    async fn tick(&self, node_id: &str) -> Result<(), Error> {
+        let config = &self.inner.config;
+        let current = self.kv.read(&config.key).await?;
+        let lease: Option<Lease> = current.clone().map(decode).transpose()?;
+
+        let next = match &lease {
+            None => Lease {
+                leader: node_id.to_string(),
+                term: 1,
+                seq: 0,
+            },
+            Some(lease) if lease.leader == node_id => Lease {
+                seq: lease.seq + 1,
+                ..lease.clone()
+            },
+            Some(lease) => {
+                let expired = {
+                    let mut state = self.inner.state.lock().unwrap();
+                    state.leading_until = None;
+                    match &state.observed {
+                        Some((value, since)) if Some(value) == current.as_ref() => {
+                            since.elapsed() >= config.lease_duration
+                        }
+                        _ => {
+                            state.observed = current.clone().map(|value| (value, Instant::now()));
+                            false
+                        }
+                    }
+                };
+                if !expired {
+                    return Ok(());
+                }
+                Lease {
+                    leader: node_id.to_string(),
+                    term: lease.term + 1,
+                    seq: 0,
+                }
+            }
+        };
+
+        let sent_at = Instant::now();
+        let to = encode(&next)?;
+        let res = match &current {
+            Some(current) => {
+                self.kv
+                    .compare_and_swap(&config.key, current, &to, false)
+                    .await
+            }
+            None => {
+                self.kv
+                    .compare_and_swap(&config.key, &Value::Null, &to, true)
+                    .await
+            }
+        };
+
+        let mut state = self.inner.state.lock().unwrap();
+        match res {
+            Ok(()) => {
+                state.leading_until = Some(sent_at + config.lease_duration);
+                state.term = next.term;
+                state.observed = Some((to, Instant::now()));
+                Ok(())
+            }
+            Err(err) => {
+                // Someone else changed the lease first. Our own lease, if we had one, is
+                // gone unless the write actually went through; either way it runs out on
+                // its own.
+                if err.is_precondition_failed() || err.is_key_does_not_exist() {
+                    state.leading_until = None;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Tell subscribers who the leader is, as far as this node knows.
+    fn publish(&self, node_id: &str) {
+        let leader = if self.is_leader() {
+            Some(node_id.to_string())
+        } else {
+            let state = self.inner.state.lock().unwrap();
+            state
+                .observed
+                .as_ref()
+                .and_then(|(value, _)| decode::<Lease>(value.clone()).ok())
+                .map(|lease| lease.leader)
+                .filter(|leader| leader != node_id)
+        };
+
+        self.inner.leader.send_if_modified(|current| {
+            if *current == leader {
+                return false;
+            }
+            tracing::info!(?leader, "Leader changed");
+            *current = leader;
+            true
+        });
+    }
+}
@@ -139,7 +139,7 @@ impl std::fmt::Display for ErrorKind {
 impl From<Error> for MessageBody {
     fn from(err: Error) -> Self {
         MessageBody::new("error")
-            .with_field("kind", err.kind as u8)
+            .with_field("code", err.kind as u8)
             .with_field("text", err.text)
     }
 }
@@ -0,0 +1,267 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde_json::Value;
+use tokio::task::JoinHandle;
+
+use crate::{
+    crdt::Crdt,
+    membership::Membership,
+    proto::{Message, MessageBody},
+    Error, Node,
+};
+
+/// Message type used to exchange state between peers.
+pub const GOSSIP: &str = "gossip";
+
+/// What each gossip message carries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Payload {
+    /// Only the part of the state the peer has not acknowledged yet.
+    #[default]
+    Delta,
+    /// The full state, whenever the peer is missing part of it.
+    Full,
+}
+
+#[derive(Debug, Clone)]
+pub struct GossipConfig {
+    interval: Duration,
+    fanout: usize,
+    timeout: Duration,
+    payload: Payload,
+    membership: Option<Membership>,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(150),
+            fanout: 4,
+            timeout: Duration::from_secs(1),
+            payload: Payload::Delta,
+            membership: None,
+        }
+    }
+}
+
+impl GossipConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time between gossip rounds.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Maximum number of peers contacted each round.
+    pub fn with_fanout(mut self, fanout: usize) -> Self {
+        self.fanout = fanout;
+        self
+    }
+
+    /// How long to wait for a peer to acknowledge a gossip message.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_payload(mut self, payload: Payload) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Only gossip to peers `membership` believes are reachable, instead of waiting for
+    /// messages to partitioned peers to time out.
+    pub fn with_membership(mut self, membership: Membership) -> Self {
+        self.membership = Some(membership);
+        self
+    }
+}
+
+struct GossipInner<T> {
+    state: Mutex<T>,
+    /// State each peer is known to have, either acknowledged or sent to us.
+    acked: Mutex<HashMap<String, T>>,
+    /// Peers with an unacknowledged gossip message outstanding.
+    pending: Mutex<HashSet<String>>,
+    round: AtomicUsize,
+    config: GossipConfig,
+}
+
+/// Anti-entropy for a [`Crdt`]: periodically pushes state to peers and merges what they push
+/// back.
+///
+/// Call [`spawn`](Self::spawn) once with the node, and route `gossip` requests to
+/// [`handle`](Self::handle).
+pub struct Gossip<T> {
+    inner: Arc<GossipInner<T>>,
+}
+
+impl<T> Clone for Gossip<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Crdt> Default for Gossip<T> {
+    fn default() -> Self {
+        Self::new(GossipConfig::default())
+    }
+}
+
+impl<T: Crdt> Gossip<T> {
+    pub fn new(config: GossipConfig) -> Self {
+        Self {
+            inner: Arc::new(GossipInner {
+                state: Mutex::new(T::default()),
+                acked: Mutex::new(HashMap::new()),
+                pending: Mutex::new(HashSet::new()),
+                round: AtomicUsize::new(0),
+                config,
+            }),
+        }
+    }
+
+    /// Apply a local update to the state. It reaches peers in the following rounds.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.inner.state.lock().unwrap())
+    }
+
+    /// Read the state without copying it.
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.inner.state.lock().unwrap())
+    }
+
+    pub fn value(&self) -> T::Value {
+        self.inner.state.lock().unwrap().value()
+    }
+
+    /// Merge state received from outside the gossip protocol.
+    pub fn merge(&self, other: &T) {
+        self.inner.state.lock().unwrap().merge(other);
+    }
+
+    /// Handle a `gossip` request from a peer.
+    pub fn handle(&self, req: &Message) -> Result<MessageBody, Error> {
+        let state: T = req
+            .body
+            .extra
+            .get("state")
+            .cloned()
+            .and_then(|state: Value| serde_json::from_value(state).ok())
+            .ok_or_else(Error::malformed_request)?;
+
+        self.merge(&state);
+        self.inner
+            .acked
+            .lock()
+            .unwrap()
+            .entry(req.src.clone())
+            .or_default()
+            .merge(&state);
+
+        Ok(MessageBody::new("gossip_ok"))
+    }
+
+    /// Start gossiping to the other nodes in the cluster once the node is initialized.
+    pub fn spawn<S>(&self, node: Node<S>) -> JoinHandle<()>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let gossip = self.clone();
+        tokio::spawn(async move {
+            let metadata = node.wait_for_init().await;
+            let peers: Vec<String> = metadata
+                .node_ids
+                .into_iter()
+                .filter(|n| n != &metadata.node_id)
+                .collect();
+
+            let mut interval = tokio::time::interval(gossip.inner.config.interval);
+            loop {
+                interval.tick().await;
+                gossip.round(&node, &peers);
+            }
+        })
+    }
+
+    /// Push state to up to `fanout` peers that are missing some of it.
+    fn round<S>(&self, node: &Node<S>, peers: &[String])
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let config = &self.inner.config;
+        let peers: Vec<String> = match &config.membership {
+            Some(membership) => peers
+                .iter()
+                .filter(|peer| membership.is_alive(peer))
+                .cloned()
+                .collect(),
+            None => peers.to_vec(),
+        };
+        if peers.is_empty() {
+            return;
+        }
+        let start = self.inner.round.fetch_add(1, Ordering::SeqCst) % peers.len();
+
+        let outgoing: Vec<(String, T)> = {
+            let state = self.inner.state.lock().unwrap();
+            let acked = self.inner.acked.lock().unwrap();
+            let mut pending = self.inner.pending.lock().unwrap();
+            let outgoing: Vec<_> = peers
+                .iter()
+                .cycle()
+                .skip(start)
+                .take(peers.len())
+                .filter(|peer| !pending.contains(*peer))
+                .filter_map(|peer| {
+                    let delta = match acked.get(peer) {
+                        Some(since) => state.delta(since)?,
+                        None => state.delta(&T::default())?,
+                    };
+                    match config.payload {
+                        Payload::Delta => Some((peer.clone(), delta)),
+                        Payload::Full => Some((peer.clone(), state.clone())),
+                    }
+                })
+                .take(config.fanout)
+                .collect();
+            pending.extend(outgoing.iter().map(|(peer, _)| peer.clone()));
+            outgoing
+        };
+
+        for (peer, payload) in outgoing {
+            let gossip = self.clone();
+            let node = node.clone();
+            tokio::spawn(async move {
+                let body = MessageBody::new(GOSSIP).with_field("state", &payload);
+                let res = node
+                    .rpc_with_timeout(peer.clone(), body, gossip.inner.config.timeout)
+                    .await;
+                match res {
+                    Ok(_) => gossip
+                        .inner
+                        .acked
+                        .lock()
+                        .unwrap()
+                        .entry(peer.clone())
+                        .or_default()
+                        .merge(&payload),
+                    Err(err) => tracing::debug!(%peer, %err, "Gossip failed"),
+                }
+                gossip.inner.pending.lock().unwrap().remove(&peer);
+            });
+        }
+    }
+}
@@ -1,91 +1,525 @@
-use serde::Serialize;
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 
-use crate::{proto::MessageBody, Error, Node};
+use crate::{error::ErrorKind, proto::MessageBody, Error, Node};
 
 /// Linearizable key-value store.
-const LIN_KV: &str = "lin-kv";
+pub(crate) const LIN_KV: &str = "lin-kv";
 /// Sequentially consistent key-value store.
-const SEQ_KV: &str = "seq-kv";
+pub(crate) const SEQ_KV: &str = "seq-kv";
 /// Last-writer-wins key-value store.
-const LWW_KV: &str = "lww-kv";
+pub(crate) const LWW_KV: &str = "lww-kv";
+
+/// Number of read and compare-and-swap rounds [`Kv::update`] attempts before giving up.
+pub const DEFAULT_UPDATE_ATTEMPTS: usize = 16;
+
+/// Hit and miss counts for a [`Kv`] read cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Fraction of reads served from the cache, or 0 before the first read.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+/// Values read or written recently, keyed by the JSON encoding of the key. A cached `None`
+/// records that the key did not exist.
+struct Cache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheEntry {
+    value: Option<(Option<Value>, Instant)>,
+    /// Bumped by every write, successful compare-and-swap and eviction through this handle,
+    /// so a read that raced with one of them can tell its reply is out of date.
+    generation: u64,
+}
+
+impl Cache {
+    fn get(&self, key: &str) -> Option<Option<Value>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key);
+        match entry.as_ref().and_then(|entry| entry.value.as_ref()) {
+            Some((value, at)) if at.elapsed() < self.ttl => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value.clone())
+            }
+            _ => {
+                if let Some(entry) = entry {
+                    entry.value = None;
+                }
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn generation(&self, key: &str) -> u64 {
+        let entries = self.entries.lock().unwrap();
+        entries.get(key).map_or(0, |entry| entry.generation)
+    }
+
+    /// Cache the reply to a read, unless the key changed since `generation` was taken.
+    fn fill(&self, key: String, value: Option<Value>, generation: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key).or_default();
+        if entry.generation == generation {
+            entry.value = Some((value, Instant::now()));
+        }
+    }
+
+    fn insert(&self, key: String, value: Option<Value>) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key).or_default();
+        entry.generation += 1;
+        entry.value = Some((value, Instant::now()));
+    }
+
+    fn remove(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key.to_string()).or_default();
+        entry.generation += 1;
+        entry.value = None;
+    }
+}
 
 /// Key-value store
-pub struct Kv<'a, S> {
-    node: &'a Node<S>,
+///
+/// Holds its own handle to the node, so it can be cloned into node state and spawned tasks.
+/// Clones share the read cache, if any.
+pub struct Kv<S> {
+    node: Node<S>,
     ty: &'static str,
+    cache: Option<Arc<Cache>>,
 }
 
-impl<'a, S> Kv<'a, S> {
-    const fn new(node: &'a Node<S>, ty: &'static str) -> Self {
-        Self { node, ty }
+impl<S> Clone for Kv<S> {
+    fn clone(&self) -> Self {
+        Self {
+            node: self.node.clone(),
+            ty: self.ty,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<S> Kv<S> {
+    fn new(node: &Node<S>, ty: &'static str) -> Self {
+        Self {
+            node: node.clone(),
+            ty,
+            cache: None,
+        }
     }
 
     /// Create a new linearizable key-value store
-    pub const fn new_lin_kv(node: &'a Node<S>) -> Self {
+    pub fn new_lin_kv(node: &Node<S>) -> Self {
         Self::new(node, LIN_KV)
     }
 
     /// Create a new sequentially consistent key-value store
-    pub const fn new_seq_kv(node: &'a Node<S>) -> Self {
+    pub fn new_seq_kv(node: &Node<S>) -> Self {
         Self::new(node, SEQ_KV)
     }
 
     /// Create a new last-writer-wins key-value store
-    pub const fn new_lww_kv(node: &'a Node<S>) -> Self {
+    pub fn new_lww_kv(node: &Node<S>) -> Self {
         Self::new(node, LWW_KV)
     }
 
+    /// Serve reads from a local cache for up to `ttl` after a value was last read or written
+    /// through this handle. Writes and successful compare-and-swaps update the cache, and a
+    /// failed compare-and-swap evicts the key.
+    ///
+    /// Ignored for lin-kv, whose reads must never be stale.
+    pub fn with_cache(mut self, ttl: Duration) -> Self {
+        if self.ty != LIN_KV {
+            self.cache = Some(Arc::new(Cache {
+                ttl,
+                entries: Mutex::new(HashMap::new()),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+            }));
+        }
+        self
+    }
+
+    /// Read cache hits and misses, or `None` if caching is disabled.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| CacheStats {
+            hits: cache.hits.load(Ordering::Relaxed),
+            misses: cache.misses.load(Ordering::Relaxed),
+        })
+    }
+
+    pub(crate) fn node(&self) -> &Node<S> {
+        &self.node
+    }
+
+    /// View the store as holding keys of type `K` and values of type `V`.
+    pub fn typed<K, V>(self) -> TypedKv<S, K, V> {
+        TypedKv::new(self)
+    }
+
     /// Read a value from the key-value store
-    pub async fn read(&self, key: &str) -> Result<Option<Value>, Error> {
+    pub async fn read(&self, key: impl Serialize) -> Result<Option<Value>, Error> {
+        let key = encode(key)?;
+        let mut generation = 0;
+        if let Some(cache) = &self.cache {
+            if let Some(value) = cache.get(&key.to_string()) {
+                return Ok(value);
+            }
+            generation = cache.generation(&key.to_string());
+        }
+
         let message = self
             .node
             .rpc(
                 self.ty.into(),
-                MessageBody::new("read").with_field("key", key),
+                MessageBody::new("read").with_field("key", &key),
             )
             .await;
 
-        match message {
-            Ok(mut message) => Ok(Some(message.body.extra.remove("value").unwrap())),
-            Err(err) if err.is_key_does_not_exist() => Ok(None),
-            Err(err) => Err(err),
+        let value = match message {
+            Ok(mut message) => match message.body.extra.remove("value") {
+                Some(value) => Some(value),
+                None => return Err(Error::new(ErrorKind::Crash, "read_ok without a value")),
+            },
+            Err(err) if err.is_key_does_not_exist() => None,
+            Err(err) => return Err(err),
+        };
+
+        if let Some(cache) = &self.cache {
+            cache.fill(key.to_string(), value.clone(), generation);
         }
+        Ok(value)
     }
 
     /// Write a value to the key-value store
-    pub async fn write(&self, key: &str, value: impl Serialize) -> Result<(), Error> {
+    pub async fn write(&self, key: impl Serialize, value: impl Serialize) -> Result<(), Error> {
+        let key = encode(key)?;
+        let value = encode(value)?;
         self.node
             .rpc(
                 self.ty.into(),
                 MessageBody::new("write")
-                    .with_field("key", key)
-                    .with_field("value", value),
+                    .with_field("key", &key)
+                    .with_field("value", &value),
             )
             .await?;
 
+        if let Some(cache) = &self.cache {
+            cache.insert(key.to_string(), Some(value));
+        }
         Ok(())
     }
 
     /// Compare and swap a value in the key-value store
     pub async fn compare_and_swap(
         &self,
-        key: &str,
+        key: impl Serialize,
         from: &Value,
         to: &Value,
         create_if_not_exists: bool,
     ) -> Result<(), Error> {
-        self.node
+        let key = encode(key)?;
+        let res = self
+            .node
             .rpc(
                 self.ty.into(),
-                MessageBody::new("compare_and_swap")
-                    .with_field("key", key)
+                MessageBody::new("cas")
+                    .with_field("key", &key)
                     .with_field("from", from)
                     .with_field("to", to)
                     .with_field("create_if_not_exists", create_if_not_exists),
             )
-            .await?;
+            .await;
 
-        Ok(())
+        if let Some(cache) = &self.cache {
+            match &res {
+                Ok(_) => cache.insert(key.to_string(), Some(to.clone())),
+                Err(_) => cache.remove(&key.to_string()),
+            }
+        }
+        res.map(|_| ())
+    }
+
+    /// Read several keys concurrently. Results are in the same order as `keys`, each with its
+    /// own error.
+    pub async fn read_many<K: Serialize>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Vec<Result<Option<Value>, Error>> {
+        join_all(keys.into_iter().map(|key| self.read(key))).await
     }
+
+    /// Write several keys concurrently. Results are in the same order as `entries`, each with
+    /// its own error.
+    pub async fn write_many<K: Serialize, V: Serialize>(
+        &self,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Vec<Result<(), Error>> {
+        join_all(
+            entries
+                .into_iter()
+                .map(|(key, value)| self.write(key, value)),
+        )
+        .await
+    }
+
+    /// Queue up a mix of operations to send concurrently.
+    pub fn pipeline(&self) -> Pipeline<S> {
+        Pipeline {
+            kv: self.clone(),
+            ops: Vec::new(),
+        }
+    }
+
+    /// Atomically replace the value of a key with `f(old)`, creating the key if it does not
+    /// exist, and return the new value.
+    ///
+    /// `f` may be called several times if other writers race with this one.
+    pub async fn update<V, F>(&self, key: impl Serialize, f: F) -> Result<V, Error>
+    where
+        V: Serialize + DeserializeOwned,
+        F: FnMut(Option<V>) -> V,
+    {
+        self.update_with_attempts(key, DEFAULT_UPDATE_ATTEMPTS, f)
+            .await
+    }
+
+    /// Like [`update`](Self::update), giving up after `attempts` conflicting writes.
+    pub async fn update_with_attempts<V, F>(
+        &self,
+        key: impl Serialize,
+        attempts: usize,
+        mut f: F,
+    ) -> Result<V, Error>
+    where
+        V: Serialize + DeserializeOwned,
+        F: FnMut(Option<V>) -> V,
+    {
+        let key = encode(key)?;
+        let mut last_err = Error::precondition_failed();
+
+        for _ in 0..attempts {
+            let old = self.read(&key).await?;
+            let new = f(old.clone().map(decode).transpose()?);
+            let to = encode(&new)?;
+
+            let res = match &old {
+                Some(old) => self.compare_and_swap(&key, old, &to, false).await,
+                None => self.compare_and_swap(&key, &Value::Null, &to, true).await,
+            };
+
+            match res {
+                Ok(()) => return Ok(new),
+                Err(err) if err.is_precondition_failed() || err.is_key_does_not_exist() => {
+                    last_err = err;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(last_err)
+    }
+}
+
+enum PipelineOp {
+    Read(Value),
+    Write(Value, Value),
+    Cas(Value, Value, Value, bool),
+}
+
+/// A batch of key-value operations sent concurrently, created by [`Kv::pipeline`].
+///
+/// There is no ordering between the operations in a pipeline, even on the same key.
+pub struct Pipeline<S> {
+    kv: Kv<S>,
+    ops: Vec<Result<PipelineOp, Error>>,
+}
+
+impl<S> Pipeline<S> {
+    pub fn read(mut self, key: impl Serialize) -> Self {
+        self.ops.push(encode(key).map(PipelineOp::Read));
+        self
+    }
+
+    pub fn write(mut self, key: impl Serialize, value: impl Serialize) -> Self {
+        let op = encode(key).and_then(|key| Ok(PipelineOp::Write(key, encode(value)?)));
+        self.ops.push(op);
+        self
+    }
+
+    pub fn compare_and_swap(
+        mut self,
+        key: impl Serialize,
+        from: impl Serialize,
+        to: impl Serialize,
+        create_if_not_exists: bool,
+    ) -> Self {
+        let op = encode(key).and_then(|key| {
+            Ok(PipelineOp::Cas(
+                key,
+                encode(from)?,
+                encode(to)?,
+                create_if_not_exists,
+            ))
+        });
+        self.ops.push(op);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl<S> Pipeline<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Send every operation and wait for all of them. Results are in the order the
+    /// operations were added: the value for reads, and `None` for writes and
+    /// compare-and-swaps.
+    pub async fn run(self) -> Vec<Result<Option<Value>, Error>> {
+        let kv = &self.kv;
+        join_all(self.ops.into_iter().map(|op| async move {
+            match op? {
+                PipelineOp::Read(key) => kv.read(&key).await,
+                PipelineOp::Write(key, value) => kv.write(&key, &value).await.map(|_| None),
+                PipelineOp::Cas(key, from, to, create) => kv
+                    .compare_and_swap(&key, &from, &to, create)
+                    .await
+                    .map(|_| None),
+            }
+        }))
+        .await
+    }
+}
+
+/// Key-value store with typed keys and values.
+///
+/// Values are encoded with serde, and values that fail to decode are reported as errors.
+pub struct TypedKv<S, K, V> {
+    kv: Kv<S>,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<S, K, V> Clone for TypedKv<S, K, V> {
+    fn clone(&self) -> Self {
+        Self::new(self.kv.clone())
+    }
+}
+
+impl<S, K, V> TypedKv<S, K, V> {
+    pub fn new(kv: Kv<S>) -> Self {
+        Self {
+            kv,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, K, V> TypedKv<S, K, V>
+where
+    K: Serialize,
+    V: Serialize + DeserializeOwned,
+{
+    /// Read a value, returning `None` if the key does not exist.
+    pub async fn read(&self, key: &K) -> Result<Option<V>, Error> {
+        self.kv.read(key).await?.map(decode).transpose()
+    }
+
+    /// Write a value
+    pub async fn write(&self, key: &K, value: &V) -> Result<(), Error> {
+        self.kv.write(key, value).await
+    }
+
+    /// Read several keys concurrently. See [`Kv::read_many`].
+    pub async fn read_many<'k>(
+        &self,
+        keys: impl IntoIterator<Item = &'k K>,
+    ) -> Vec<Result<Option<V>, Error>>
+    where
+        K: 'k,
+    {
+        self.kv
+            .read_many(keys)
+            .await
+            .into_iter()
+            .map(|res| res?.map(decode).transpose())
+            .collect()
+    }
+
+    /// Write several keys concurrently. See [`Kv::write_many`].
+    pub async fn write_many<'e>(
+        &self,
+        entries: impl IntoIterator<Item = (&'e K, &'e V)>,
+    ) -> Vec<Result<(), Error>>
+    where
+        K: 'e,
+        V: 'e,
+    {
+        self.kv.write_many(entries).await
+    }
+
+    /// Compare and swap a value
+    pub async fn compare_and_swap(
+        &self,
+        key: &K,
+        from: &V,
+        to: &V,
+        create_if_not_exists: bool,
+    ) -> Result<(), Error> {
+        self.kv
+            .compare_and_swap(key, &encode(from)?, &encode(to)?, create_if_not_exists)
+            .await
+    }
+
+    /// Read cache hits and misses. See [`Kv::cache_stats`].
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.kv.cache_stats()
+    }
+
+    /// Atomically replace the value of a key with `f(old)`. See [`Kv::update`].
+    pub async fn update(&self, key: &K, f: impl FnMut(Option<V>) -> V) -> Result<V, Error> {
+        self.kv.update(key, f).await
+    }
+}
+
+pub(crate) fn encode(value: impl Serialize) -> Result<Value, Error> {
+    serde_json::to_value(value)
+        .map_err(|err| Error::new(ErrorKind::MalformedRequest, err.to_string()))
+}
+
+pub(crate) fn decode<V: DeserializeOwned>(value: Value) -> Result<V, Error> {
+    serde_json::from_value(value).map_err(|err| Error::new(ErrorKind::Crash, err.to_string()))
 }
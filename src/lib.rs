@@ -3,8 +3,9 @@ use std::{
     future::Future,
     sync::{
         atomic::{AtomicU32, Ordering},
-        Arc,
+        Arc, OnceLock,
     },
+    time::{Duration, Instant, SystemTime},
 };
 
 pub use error::Error;
@@ -12,14 +13,41 @@ use proto::{IntoBody, Message};
 use serde_json::Value;
 use tokio::{
     io::{AsyncBufReadExt as _, AsyncWriteExt, BufReader, BufWriter},
-    sync::{oneshot, MappedMutexGuard, Mutex, MutexGuard},
+    sync::{mpsc, oneshot, MappedMutexGuard, Mutex, MutexGuard, Notify},
+    task::JoinHandle,
+};
+use tracing::Instrument as _;
+
+use crate::{
+    clock::{Clock, DynClock},
+    metrics::{Metrics, Sink, Snapshot, DEFAULT_DUMP_INTERVAL},
+    otlp::{FinishedSpan, SpanExporter, SpanKind},
+    proto::{InitMessage, MessageBody},
+    record::{Direction, Recorder},
+    replay::{Replay, REPLAY_ENV},
+    serve::{Admission, KeyedQueues, Limiter, LoadCounters, Overload, ServeConfig, ServeStats},
+    trace::{request_span, rpc_span, SpanContext},
 };
 
-use crate::proto::{InitMessage, MessageBody};
-
+pub mod clock;
+pub mod crdt;
+pub mod election;
 pub mod error;
+pub mod gossip;
 pub mod kv;
+pub mod membership;
+pub mod metrics;
+pub mod otlp;
+pub mod paxos;
 pub mod proto;
+pub mod raft;
+pub mod record;
+pub mod replay;
+pub mod serve;
+pub mod sim;
+pub mod trace;
+pub mod tso;
+pub mod txn;
 
 #[derive(Clone, Debug)]
 pub struct NodeMetadata {
@@ -33,26 +61,33 @@ pub struct NodeInner<S> {
     node_data: Mutex<Option<NodeMetadata>>,
     channel_map: Mutex<HashMap<u32, oneshot::Sender<Result<Message, Error>>>>,
     msg_ctr: AtomicU32,
+    load: Arc<LoadCounters>,
+    clock: OnceLock<Box<dyn DynClock>>,
+    init: Notify,
+    output: OnceLock<mpsc::UnboundedSender<Message>>,
+    recorder: OnceLock<Recorder>,
+    spans: OnceLock<SpanExporter>,
+    metrics: Metrics,
 }
 
-#[derive(Clone)]
 pub struct Node<S> {
     inner: Arc<NodeInner<S>>,
 }
 
-impl Node<()> {
-    pub fn new() -> Self {
+impl<S> Clone for Node<S> {
+    fn clone(&self) -> Self {
         Self {
-            inner: Arc::new(NodeInner {
-                state: (),
-                node_data: Mutex::new(None),
-                channel_map: Mutex::new(HashMap::new()),
-                msg_ctr: AtomicU32::new(0),
-            }),
+            inner: self.inner.clone(),
         }
     }
 }
 
+impl Node<()> {
+    pub fn new() -> Self {
+        Self::with_state(())
+    }
+}
+
 impl<S> Default for Node<S>
 where
     S: Default,
@@ -69,7 +104,15 @@ impl<S> Node<S> {
                 state,
                 node_data: Mutex::new(None),
                 channel_map: Mutex::new(HashMap::new()),
-                msg_ctr: AtomicU32::new(0),
+                // Message ids start at 1, since a zero `in_reply_to` marks a request.
+                msg_ctr: AtomicU32::new(1),
+                load: Arc::default(),
+                clock: OnceLock::new(),
+                init: Notify::new(),
+                output: OnceLock::new(),
+                recorder: OnceLock::new(),
+                spans: OnceLock::new(),
+                metrics: Metrics::default(),
             }),
         }
     }
@@ -86,6 +129,21 @@ impl<S> Node<S> {
         })
     }
 
+    /// Wait until the node has received its `init` message.
+    pub async fn wait_for_init(&self) -> NodeMetadata {
+        loop {
+            let notified = self.inner.init.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if let Some(node_data) = self.inner.node_data.lock().await.clone() {
+                return node_data;
+            }
+
+            notified.await;
+        }
+    }
+
     pub async fn node_metadata<'a>(&'a self) -> MappedMutexGuard<'a, NodeMetadata> {
         MutexGuard::map(self.inner.node_data.lock().await, |node_data| {
             node_data.as_mut().unwrap()
@@ -96,8 +154,98 @@ impl<S> Node<S> {
         &self.inner.state
     }
 
+    /// Stamp every outgoing message with `clock` and merge the timestamps of incoming ones.
+    ///
+    /// Keep a clone of an `Arc`-wrapped clock in the node state to read it from handlers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node already has a clock.
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        if self.inner.clock.set(Box::new(clock)).is_err() {
+            panic!("Node already has a clock");
+        }
+        self
+    }
+
+    /// Record every message the node receives or sends.
+    ///
+    /// Without this, [`serve`](Self::serve) records to the file named by
+    /// [`TRACE_ENV`](record::TRACE_ENV) if it is set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node already has a recorder.
+    pub fn with_recorder(self, recorder: Recorder) -> Self {
+        if self.inner.recorder.set(recorder).is_err() {
+            panic!("Node already has a recorder");
+        }
+        self
+    }
+
+    /// Export a span for every request the node handles and every RPC it sends.
+    ///
+    /// Without this, [`serve`](Self::serve) exports to the file named by
+    /// [`SPANS_ENV`](otlp::SPANS_ENV) if it is set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node already has a span exporter.
+    pub fn with_span_exporter(self, exporter: SpanExporter) -> Self {
+        if self.inner.spans.set(exporter).is_err() {
+            panic!("Node already has a span exporter");
+        }
+        self
+    }
+
+    /// Current request load on the node.
+    pub fn serve_stats(&self) -> ServeStats {
+        self.inner.load.stats()
+    }
+
+    /// Messages sent and received, RPC latencies and errors since the node started.
+    pub async fn metrics(&self) -> Snapshot {
+        let node_id = self
+            .inner
+            .node_data
+            .lock()
+            .await
+            .as_ref()
+            .map(|node_data| node_data.node_id.clone());
+        self.inner.metrics.snapshot(node_id, self.serve_stats())
+    }
+
+    /// Send outgoing messages to a channel instead of stdout.
+    pub(crate) fn set_output(&self, output: mpsc::UnboundedSender<Message>) {
+        if self.inner.output.set(output).is_err() {
+            panic!("Node already has an output");
+        }
+    }
+
+    /// Write an outgoing message, stamping it with the node's clock.
+    async fn write(&self, mut msg: Message) {
+        if let Some(clock) = self.inner.clock.get() {
+            clock.stamp(&msg.src, &mut msg.body);
+        }
+        if let Some(recorder) = self.inner.recorder.get() {
+            recorder.record(Direction::Out, &msg);
+        }
+        self.inner.metrics.sent(&msg);
+        match self.inner.output.get() {
+            Some(output) => {
+                let _ = output.send(msg);
+            }
+            None => write_message(msg).await,
+        }
+    }
+
     /// Send a message to a destination node with no expectation of a reply.
-    pub async fn send(&self, dst: String, body: MessageBody) {
+    ///
+    /// Inside a handler, the message carries the handler's trace context.
+    pub async fn send(&self, dst: String, mut body: MessageBody) {
+        if let Some(ctx) = SpanContext::current() {
+            ctx.inject(&mut body);
+        }
         tracing::info!(%dst, ?body, "Sending message");
         let msg_id = self.inner.msg_ctr.fetch_add(1, Ordering::SeqCst);
         let msg = Message {
@@ -105,11 +253,87 @@ impl<S> Node<S> {
             dst,
             body: MessageBody { msg_id, ..body },
         };
-        write_message(msg).await;
+        self.write(msg).await;
     }
 
     /// Send a message to a destination node and wait for a reply.
     pub async fn rpc(&self, dst: String, body: MessageBody) -> Result<Message, Error> {
+        self.traced_rpc(dst, body, None).await
+    }
+
+    /// Send a message to a destination node and wait up to `timeout` for a reply.
+    ///
+    /// Returns a `timeout` error if no reply arrives in time; a late reply is discarded.
+    pub async fn rpc_with_timeout(
+        &self,
+        dst: String,
+        body: MessageBody,
+        timeout: Duration,
+    ) -> Result<Message, Error> {
+        self.traced_rpc(dst, body, Some(timeout)).await
+    }
+
+    /// Send an RPC in its own span, a child of the current handler's span if there is one.
+    async fn traced_rpc(
+        &self,
+        dst: String,
+        mut body: MessageBody,
+        timeout: Option<Duration>,
+    ) -> Result<Message, Error> {
+        let ctx = SpanContext::current().map_or_else(SpanContext::root, |ctx| ctx.child());
+        ctx.inject(&mut body);
+        let span = rpc_span(&dst, &body.ty, &ctx);
+        let ty = body.ty.clone();
+
+        async move {
+            let start = Instant::now();
+            let started_at = SystemTime::now();
+            let peer = dst.clone();
+            let (msg_id, rx) = self.start_rpc(dst, body).await;
+
+            let res = match timeout {
+                None => rx.await.unwrap().and_then(rpc_result),
+                Some(timeout) => match tokio::time::timeout(timeout, rx).await {
+                    Ok(res) => res.unwrap().and_then(rpc_result),
+                    Err(_) => {
+                        self.inner.channel_map.lock().await.remove(&msg_id);
+                        Err(Error::timeout())
+                    }
+                },
+            };
+            self.record_rpc(&ty, start, &res);
+            self.export_span(FinishedSpan {
+                ctx,
+                name: ty,
+                kind: SpanKind::Client,
+                start: started_at,
+                end: SystemTime::now(),
+                peer,
+                error: res.as_ref().err().map(ToString::to_string),
+            })
+            .await;
+            res
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn export_span(&self, span: FinishedSpan) {
+        if let Some(exporter) = self.inner.spans.get() {
+            exporter.export(&self.id().await, &span);
+        }
+    }
+
+    fn record_rpc(&self, ty: &str, start: Instant, res: &Result<Message, Error>) {
+        let err = res.as_ref().err().map(|err| err.kind);
+        self.inner.metrics.rpc(ty, start.elapsed(), err);
+    }
+
+    async fn start_rpc(
+        &self,
+        dst: String,
+        body: MessageBody,
+    ) -> (u32, oneshot::Receiver<Result<Message, Error>>) {
         let msg_id = self.inner.msg_ctr.fetch_add(1, Ordering::SeqCst);
 
         let (tx, rx) = oneshot::channel();
@@ -120,15 +344,9 @@ impl<S> Node<S> {
             dst,
             body: MessageBody { msg_id, ..body },
         };
-        write_message(msg).await;
-
-        let res = rx.await.unwrap()?;
+        self.write(msg).await;
 
-        if res.ty() == "error" {
-            Err(Error::from(res.body))
-        } else {
-            Ok(res)
-        }
+        (msg_id, rx)
     }
 }
 
@@ -136,91 +354,297 @@ impl<S> Node<S>
 where
     S: Clone + Send + Sync + 'static,
 {
-    pub async fn serve<'a, F, Fut, B>(&self, f: F)
+    /// Serve requests from stdin with the default [`ServeConfig`].
+    pub async fn serve<F, Fut, B>(&self, f: F)
+    where
+        F: Fn(Node<S>, Message) -> Fut + Copy + Send + Sync + 'static,
+        Fut: Future<Output = B> + Send + 'static,
+        B: IntoBody + 'static,
+    {
+        self.serve_with(ServeConfig::default(), f).await
+    }
+
+    /// Serve requests from stdin, scheduling handlers according to `config`.
+    ///
+    /// If [`REPLAY_ENV`] names a trace, replays it instead and prints how the node's output
+    /// differs from the recording to stderr.
+    pub async fn serve_with<F, Fut, B>(&self, config: ServeConfig, f: F)
     where
         F: Fn(Node<S>, Message) -> Fut + Copy + Send + Sync + 'static,
         Fut: Future<Output = B> + Send + 'static,
-        B: IntoBody,
+        B: IntoBody + 'static,
     {
-        tracing_subscriber::fmt()
-            .with_ansi(false)
-            .with_writer(std::io::stderr)
-            .init();
+        config.logging.init();
+
+        if let Some(path) = std::env::var_os(REPLAY_ENV) {
+            match Replay::from_file(&path) {
+                Ok(replay) => eprint!("{}", replay.run_with(self, config, f).await),
+                Err(err) => tracing::error!(?path, %err, "Failed to read trace"),
+            }
+            return;
+        }
+
+        if let Some(recorder) = Recorder::from_env() {
+            let _ = self.inner.recorder.set(recorder);
+        }
+        if let Some(exporter) = SpanExporter::from_env() {
+            let _ = self.inner.spans.set(exporter);
+        }
+        let sink = Sink::from_env();
+        if let Some(sink) = &sink {
+            self.spawn_metrics_dump(DEFAULT_DUMP_INTERVAL, sink.clone());
+        }
+
+        let server = Server::new(self.clone(), config, f);
 
         let buf = BufReader::new(tokio::io::stdin());
         let mut lines = buf.lines();
 
         while let Ok(Some(line)) = lines.next_line().await {
-            let self_ = self.clone();
-
-            tokio::spawn(async move {
-                let req: Message = match serde_json::from_str(&line) {
-                    Ok(req) => req,
-                    Err(err) => {
-                        tracing::error!(?err, "Failed to parse message");
-                        write_message(Message {
-                            src: self_.id().await.clone(),
-                            dst: "error".to_string(),
-                            body: Error::malformed_request().into(),
-                        })
-                        .await;
-                        return;
-                    }
-                };
-
-                let req_id = req.body.msg_id;
-                tracing::info!(%req_id, ?req, "Received request");
-
-                let dst = req.src.clone();
-
-                if req.ty() == "init" {
-                    let InitMessage { node_id, node_ids }: InitMessage =
-                        serde_json::from_value(Value::Object(req.body.extra)).unwrap();
-
-                    self_.inner.node_data.lock().await.replace(NodeMetadata {
-                        node_id: node_id.clone(),
-                        node_ids,
-                    });
-
-                    let msg = Message {
-                        src: node_id,
-                        dst,
-                        body: MessageBody {
-                            ty: "init_ok".to_string(),
-                            in_reply_to: req_id,
-                            ..Default::default()
-                        },
-                    };
-
-                    write_message(msg).await;
-                } else if req.body.in_reply_to != 0 {
-                    if let Some(tx) = self_
-                        .inner
-                        .channel_map
-                        .lock()
-                        .await
-                        .remove(&req.body.in_reply_to)
-                    {
-                        tx.send(Ok(req)).unwrap();
+            let req: Message = match serde_json::from_str(&line) {
+                Ok(req) => req,
+                Err(err) => {
+                    tracing::error!(?err, "Failed to parse message");
+                    self.write(Message {
+                        src: self.id().await.clone(),
+                        dst: "error".to_string(),
+                        body: Error::malformed_request().into(),
+                    })
+                    .await;
+                    continue;
+                }
+            };
+
+            server.receive(req).await;
+        }
+
+        if let Some(sink) = &sink {
+            sink.write(&self.metrics().await);
+        }
+    }
+
+    /// Dump [`metrics`](Self::metrics) to `sink` every `interval`.
+    ///
+    /// [`serve`](Self::serve) starts this on its own when [`METRICS_ENV`](metrics::METRICS_ENV)
+    /// is set, and dumps once more when stdin closes.
+    pub fn spawn_metrics_dump(&self, interval: Duration, sink: Sink) -> JoinHandle<()> {
+        let node = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                sink.write(&node.metrics().await);
+            }
+        })
+    }
+
+    /// Serve messages delivered over a channel instead of stdin, until the channel closes.
+    pub(crate) async fn serve_channel<F, Fut, B>(
+        &self,
+        config: ServeConfig,
+        mut rx: mpsc::UnboundedReceiver<Message>,
+        f: F,
+    ) where
+        F: Fn(Node<S>, Message) -> Fut + Copy + Send + Sync + 'static,
+        Fut: Future<Output = B> + Send + 'static,
+        B: IntoBody + 'static,
+    {
+        let server = Server::new(self.clone(), config, f);
+        while let Some(req) = rx.recv().await {
+            server.receive(req).await;
+        }
+    }
+
+    async fn init(&self, req: Message) {
+        let InitMessage { node_id, node_ids }: InitMessage =
+            serde_json::from_value(Value::Object(req.body.extra)).unwrap();
+
+        self.inner.node_data.lock().await.replace(NodeMetadata {
+            node_id: node_id.clone(),
+            node_ids,
+        });
+        self.inner.init.notify_waiters();
+
+        let msg = Message {
+            src: node_id,
+            dst: req.src,
+            body: MessageBody {
+                ty: "init_ok".to_string(),
+                in_reply_to: req.body.msg_id,
+                ..Default::default()
+            },
+        };
+
+        self.write(msg).await;
+    }
+
+    async fn handle<F, Fut, B>(self, f: F, req: Message, admission: Admission)
+    where
+        F: Fn(Node<S>, Message) -> Fut,
+        Fut: Future<Output = B>,
+        B: IntoBody + 'static,
+    {
+        let ctx = SpanContext::for_message(&req.body);
+        let span = request_span(&req, Some(&ctx));
+        let run = async move {
+            let _running = admission.run().await;
+            let started_at = SystemTime::now();
+
+            let req_id = req.body.msg_id;
+            let src = self.id().await.clone();
+            let dst = req.src.clone();
+            let ty = req.ty().to_string();
+
+            let body = f(self.clone(), req).await.into_body();
+            self.export_span(FinishedSpan {
+                ctx,
+                name: ty,
+                kind: SpanKind::Server,
+                start: started_at,
+                end: SystemTime::now(),
+                peer: dst.clone(),
+                error: body.as_ref().filter(|body| body.ty == "error").map(|body| {
+                    match body.extra.get("text").and_then(Value::as_str) {
+                        Some(text) => text.to_string(),
+                        None => "error".to_string(),
                     }
-                } else {
-                    let src = self_.id().await.clone();
+                }),
+            })
+            .await;
+
+            let Some(mut body) = body else {
+                return;
+            };
 
-                    let mut body = match f(self_, req).await.into_body() {
-                        Some(body) => body,
-                        None => return,
-                    };
+            body.in_reply_to = req_id;
 
-                    body.in_reply_to = req_id;
+            let msg = Message { src, dst, body };
 
-                    let msg = Message { src, dst, body };
+            tracing::info!(%req_id, ?msg, "Sending response");
+            self.write(msg).await;
+        };
+        ctx.scope(run.instrument(span)).await
+    }
 
-                    tracing::info!(%req_id, ?msg, "Sending response");
-                    write_message(msg).await;
-                };
-            });
+    /// Reply to a request without running the handler.
+    async fn reply(&self, req: Message, mut body: MessageBody) {
+        body.in_reply_to = req.body.msg_id;
+        let msg = Message {
+            src: self.id().await.clone(),
+            dst: req.src,
+            body,
+        };
+        self.write(msg).await;
+    }
+}
+
+/// State for one serve loop, shared by every message it receives.
+struct Server<S, F> {
+    node: Node<S>,
+    config: ServeConfig,
+    limiter: Limiter,
+    queues: Arc<KeyedQueues>,
+    f: F,
+}
+
+impl<S, F> Server<S, F>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn new(node: Node<S>, config: ServeConfig, f: F) -> Self {
+        Self {
+            limiter: Limiter::new(&config, node.inner.load.clone()),
+            queues: Arc::new(KeyedQueues::default()),
+            node,
+            config,
+            f,
         }
     }
+
+    /// Process an incoming message inside its request span.
+    async fn receive<Fut, B>(&self, req: Message)
+    where
+        F: Fn(Node<S>, Message) -> Fut + Copy + Send + Sync + 'static,
+        Fut: Future<Output = B> + Send + 'static,
+        B: IntoBody + 'static,
+    {
+        let span = request_span(&req, None);
+        self.route(req).instrument(span).await
+    }
+
+    /// Route an incoming message: `init`, a reply to an outstanding RPC, or a request for
+    /// the handler.
+    async fn route<Fut, B>(&self, req: Message)
+    where
+        F: Fn(Node<S>, Message) -> Fut + Copy + Send + Sync + 'static,
+        Fut: Future<Output = B> + Send + 'static,
+        B: IntoBody + 'static,
+    {
+        let node = &self.node;
+        let f = self.f;
+
+        tracing::info!(req_id = %req.body.msg_id, ?req, "Received request");
+
+        if let Some(recorder) = node.inner.recorder.get() {
+            recorder.record(Direction::In, &req);
+        }
+        node.inner.metrics.received(&req);
+
+        if let Some(clock) = node.inner.clock.get() {
+            clock.merge(&req.dst, &req.body);
+        }
+
+        if req.ty() == "init" {
+            node.init(req).await;
+        } else if req.body.in_reply_to != 0 {
+            if let Some(tx) = node
+                .inner
+                .channel_map
+                .lock()
+                .await
+                .remove(&req.body.in_reply_to)
+            {
+                let _ = tx.send(Ok(req));
+            }
+        } else {
+            let Some(admission) = self.limiter.admit() else {
+                tracing::warn!(req_id = %req.body.msg_id, "Node overloaded");
+                if self.config.overload == Overload::Reject {
+                    node.reply(req, Error::temporarily_unavailable().into())
+                        .await;
+                }
+                return;
+            };
+
+            match self.config.dispatch.key(&req) {
+                None => {
+                    tokio::spawn(node.clone().handle(f, req, admission));
+                }
+                Some(key) => {
+                    if self.queues.push(&key, (req, admission)) {
+                        let node = node.clone();
+                        let queues = self.queues.clone();
+                        tokio::spawn(async move {
+                            while let Some((req, admission)) = queues.pop(&key) {
+                                // Run each handler in its own task so a panic does not leave
+                                // the key stuck with no worker.
+                                let _ = tokio::spawn(node.clone().handle(f, req, admission)).await;
+                            }
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn rpc_result(res: Message) -> Result<Message, Error> {
+    if res.ty() == "error" {
+        Err(Error::from(res.body))
+    } else {
+        Ok(res)
+    }
 }
 
 async fn write_message(msg: Message) {
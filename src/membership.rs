@@ -0,0 +1,344 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::future::select_ok;
+use rand::seq::SliceRandom;
+use tokio::{
+    sync::broadcast,
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+
+use crate::{
+    proto::{Message, MessageBody},
+    Error, Node,
+};
+
+/// Message type for direct liveness probes.
+pub const PING: &str = "swim_ping";
+/// Message type asking a peer to probe another node on our behalf.
+pub const PING_REQ: &str = "swim_ping_req";
+
+/// Number of unread changes kept for each subscriber before the oldest are dropped.
+const CHANGES_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct MembershipConfig {
+    probe_interval: Duration,
+    probe_timeout: Duration,
+    indirect_probes: usize,
+    suspicion_timeout: Duration,
+}
+
+impl Default for MembershipConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval: Duration::from_millis(200),
+            probe_timeout: Duration::from_millis(100),
+            indirect_probes: 2,
+            suspicion_timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+impl MembershipConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time between probes. Each probe targets one peer.
+    pub fn with_probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+
+    /// How long to wait for a direct ping to be acknowledged. Indirect probes get twice as
+    /// long.
+    pub fn with_probe_timeout(mut self, timeout: Duration) -> Self {
+        self.probe_timeout = timeout;
+        self
+    }
+
+    /// Number of peers asked to probe a node that missed a direct ping.
+    pub fn with_indirect_probes(mut self, indirect_probes: usize) -> Self {
+        self.indirect_probes = indirect_probes;
+        self
+    }
+
+    /// How long a node stays suspect before it is considered dead.
+    pub fn with_suspicion_timeout(mut self, timeout: Duration) -> Self {
+        self.suspicion_timeout = timeout;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberState {
+    Alive,
+    /// Missed a probe, but may still be reachable.
+    Suspect,
+    /// Stayed suspect for the whole suspicion timeout. Dead nodes are still probed, and come
+    /// back as alive once they answer.
+    Dead,
+}
+
+/// A peer changing state, as delivered to [`Membership::subscribe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MembershipChange {
+    pub node_id: String,
+    pub state: MemberState,
+}
+
+#[derive(Debug)]
+struct Member {
+    state: MemberState,
+    since: Instant,
+}
+
+struct MembershipInner {
+    members: Mutex<BTreeMap<String, Member>>,
+    changes: broadcast::Sender<MembershipChange>,
+    round: AtomicUsize,
+    config: MembershipConfig,
+}
+
+/// SWIM-style failure detector tracking which peers are reachable.
+///
+/// Each round, one peer is pinged directly. If it does not answer in time, a few other
+/// peers are asked to ping it, and it becomes suspect only if none of them get through
+/// either. Suspects that stay silent for the suspicion timeout are declared dead. Every node
+/// keeps its own view; suspicions are not disseminated to other members.
+///
+/// Call [`spawn`](Self::spawn) once with the node, and route `swim_ping` and
+/// `swim_ping_req` requests to [`handle`](Self::handle).
+pub struct Membership {
+    inner: Arc<MembershipInner>,
+}
+
+impl Clone for Membership {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for Membership {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.members()).finish()
+    }
+}
+
+impl Default for Membership {
+    fn default() -> Self {
+        Self::new(MembershipConfig::default())
+    }
+}
+
+impl Membership {
+    pub fn new(config: MembershipConfig) -> Self {
+        Self {
+            inner: Arc::new(MembershipInner {
+                members: Mutex::new(BTreeMap::new()),
+                changes: broadcast::channel(CHANGES_CAPACITY).0,
+                round: AtomicUsize::new(0),
+                config,
+            }),
+        }
+    }
+
+    /// Peers currently believed reachable. Empty until the node is initialized.
+    pub fn alive_peers(&self) -> Vec<String> {
+        self.inner
+            .members
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, member)| member.state == MemberState::Alive)
+            .map(|(node_id, _)| node_id.clone())
+            .collect()
+    }
+
+    /// Whether a peer is believed reachable. Unknown nodes, such as clients and services,
+    /// are assumed to be.
+    pub fn is_alive(&self, node_id: &str) -> bool {
+        self.state(node_id)
+            .is_none_or(|state| state == MemberState::Alive)
+    }
+
+    pub fn state(&self, node_id: &str) -> Option<MemberState> {
+        let members = self.inner.members.lock().unwrap();
+        members.get(node_id).map(|member| member.state)
+    }
+
+    /// The state of every peer.
+    pub fn members(&self) -> BTreeMap<String, MemberState> {
+        let members = self.inner.members.lock().unwrap();
+        members
+            .iter()
+            .map(|(node_id, member)| (node_id.clone(), member.state))
+            .collect()
+    }
+
+    /// Receive every change in a peer's state from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<MembershipChange> {
+        self.inner.changes.subscribe()
+    }
+
+    /// Handle a `swim_ping` or `swim_ping_req` request.
+    pub async fn handle<S>(&self, node: &Node<S>, req: &Message) -> Result<MessageBody, Error>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        // Hearing from a peer is as good as a successful probe.
+        self.set_state(&req.src, MemberState::Alive);
+
+        match req.ty() {
+            PING => Ok(MessageBody::new("swim_ping_ok")),
+            PING_REQ => {
+                let target = req
+                    .body
+                    .extra
+                    .get("target")
+                    .and_then(|target| target.as_str())
+                    .ok_or_else(Error::malformed_request)?;
+                self.ping(node, target).await?;
+                Ok(MessageBody::new("swim_ping_req_ok"))
+            }
+            _ => Err(Error::not_supported()),
+        }
+    }
+
+    /// Start probing the other nodes in the cluster once the node is initialized.
+    pub fn spawn<S>(&self, node: Node<S>) -> JoinHandle<()>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let membership = self.clone();
+        tokio::spawn(async move {
+            let metadata = node.wait_for_init().await;
+            let mut peers: Vec<String> = metadata
+                .node_ids
+                .into_iter()
+                .filter(|n| n != &metadata.node_id)
+                .collect();
+            peers.shuffle(&mut rand::thread_rng());
+
+            {
+                let now = Instant::now();
+                let mut members = membership.inner.members.lock().unwrap();
+                for peer in &peers {
+                    members.entry(peer.clone()).or_insert(Member {
+                        state: MemberState::Alive,
+                        since: now,
+                    });
+                }
+            }
+
+            let mut interval = tokio::time::interval(membership.inner.config.probe_interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                membership.expire_suspects();
+                membership.probe(&node, &peers).await;
+            }
+        })
+    }
+
+    /// Probe the next peer, directly and then through others.
+    async fn probe<S>(&self, node: &Node<S>, peers: &[String])
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        if peers.is_empty() {
+            return;
+        }
+        let target = &peers[self.inner.round.fetch_add(1, Ordering::SeqCst) % peers.len()];
+
+        if self.ping(node, target).await.is_ok() {
+            self.set_state(target, MemberState::Alive);
+            return;
+        }
+
+        let mut helpers: Vec<String> = self
+            .alive_peers()
+            .into_iter()
+            .filter(|peer| peer != target)
+            .collect();
+        helpers.shuffle(&mut rand::thread_rng());
+        helpers.truncate(self.inner.config.indirect_probes);
+
+        let timeout = self.inner.config.probe_timeout * 2;
+        let probes = helpers.into_iter().map(|helper| {
+            let body = MessageBody::new(PING_REQ).with_field("target", target);
+            Box::pin(node.rpc_with_timeout(helper, body, timeout))
+        });
+        let reached = match probes.len() {
+            0 => false,
+            _ => select_ok(probes).await.is_ok(),
+        };
+
+        if reached {
+            self.set_state(target, MemberState::Alive);
+        } else if self.state(target) == Some(MemberState::Alive) {
+            self.set_state(target, MemberState::Suspect);
+        }
+    }
+
+    async fn ping<S>(&self, node: &Node<S>, target: &str) -> Result<(), Error>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        node.rpc_with_timeout(
+            target.to_string(),
+            MessageBody::new(PING),
+            self.inner.config.probe_timeout,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Declare dead every suspect whose suspicion timeout has run out.
+    fn expire_suspects(&self) {
+        let expired: Vec<String> = {
+            let members = self.inner.members.lock().unwrap();
+            members
+                .iter()
+                .filter(|(_, member)| {
+                    member.state == MemberState::Suspect
+                        && member.since.elapsed() >= self.inner.config.suspicion_timeout
+                })
+                .map(|(node_id, _)| node_id.clone())
+                .collect()
+        };
+        for node_id in expired {
+            self.set_state(&node_id, MemberState::Dead);
+        }
+    }
+
+    /// Move a known peer to a new state, notifying subscribers if it changed.
+    fn set_state(&self, node_id: &str, state: MemberState) {
+        let mut members = self.inner.members.lock().unwrap();
+        let Some(member) = members.get_mut(node_id) else {
+            return;
+        };
+        if member.state == state {
+            return;
+        }
+
+        tracing::info!(%node_id, from = ?member.state, to = ?state, "Membership changed");
+        member.state = state;
+        member.since = Instant::now();
+        let _ = self.inner.changes.send(MembershipChange {
+            node_id: node_id.to_string(),
+            state,
+        });
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{error::ErrorKind, proto::Message, serve::ServeStats};
+
+/// Environment variable naming where [`serve`](crate::Node::serve) dumps metrics: a file
+/// path, or `stderr`.
+pub const METRICS_ENV: &str = "FLY_DIST_SYS_METRICS";
+
+/// Time between dumps started by [`serve`](crate::Node::serve).
+pub const DEFAULT_DUMP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Upper bounds of the RPC latency buckets, in microseconds. Anything slower lands in a
+/// final overflow bucket.
+const LATENCY_BUCKETS: [u64; 13] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000,
+];
+
+/// Where periodic metrics dumps go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sink {
+    Stderr,
+    /// Appended to as JSONL.
+    File(PathBuf),
+}
+
+impl Sink {
+    /// The sink named by [`METRICS_ENV`], if it is set.
+    pub fn from_env() -> Option<Self> {
+        let sink = std::env::var_os(METRICS_ENV)?;
+        Some(match sink.to_str() {
+            Some("stderr") => Self::Stderr,
+            _ => Self::File(sink.into()),
+        })
+    }
+
+    pub(crate) fn write(&self, snapshot: &Snapshot) {
+        let mut line = serde_json::to_vec(snapshot).unwrap();
+        line.push(b'\n');
+        let res = match self {
+            Sink::Stderr => std::io::stderr().write_all(&line),
+            Sink::File(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(&line)),
+        };
+        if let Err(err) = res {
+            tracing::error!(%err, "Failed to write metrics");
+        }
+    }
+}
+
+/// Latency distribution over fixed buckets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Histogram {
+    /// Count per bucket; `buckets[i]` counts samples up to the `i`th bound, and the last
+    /// entry counts everything slower.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_us: u64,
+    pub max_us: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS.len() + 1],
+            count: 0,
+            sum_us: 0,
+            max_us: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, latency: Duration) {
+        let us = latency.as_micros() as u64;
+        let bucket = LATENCY_BUCKETS.partition_point(|&bound| bound < us);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_us += us;
+        self.max_us = self.max_us.max(us);
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.sum_us / self.count))
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, or the maximum for the overflow
+    /// bucket.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                let bound = LATENCY_BUCKETS.get(i).copied().unwrap_or(self.max_us);
+                return Some(Duration::from_micros(bound.min(self.max_us)));
+            }
+        }
+        Some(Duration::from_micros(self.max_us))
+    }
+}
+
+/// Counts keyed by message type, then by peer.
+pub type Counts = BTreeMap<String, BTreeMap<String, u64>>;
+
+#[derive(Debug, Default)]
+struct Registry {
+    sent: Counts,
+    received: Counts,
+    rpc_latency: BTreeMap<String, Histogram>,
+    rpc_errors: BTreeMap<String, u64>,
+    errors_sent: BTreeMap<String, u64>,
+}
+
+/// Message counters a node updates as it sends and receives.
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    registry: Mutex<Registry>,
+    start: Instant,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            registry: Mutex::default(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Metrics {
+    pub(crate) fn sent(&self, msg: &Message) {
+        let mut registry = self.registry.lock().unwrap();
+        count(&mut registry.sent, msg.ty(), &msg.dst);
+        if msg.ty() == "error" {
+            if let Some(kind) = error_kind(msg) {
+                *registry.errors_sent.entry(kind).or_default() += 1;
+            }
+        }
+    }
+
+    pub(crate) fn received(&self, msg: &Message) {
+        count(
+            &mut self.registry.lock().unwrap().received,
+            msg.ty(),
+            &msg.src,
+        );
+    }
+
+    /// Record the outcome of an RPC of type `ty`: the time to a reply, or the error kind
+    /// if it failed. Timeouts have no latency.
+    pub(crate) fn rpc(&self, ty: &str, latency: Duration, err: Option<ErrorKind>) {
+        let mut registry = self.registry.lock().unwrap();
+        if err != Some(ErrorKind::Timeout) {
+            registry
+                .rpc_latency
+                .entry(ty.to_string())
+                .or_default()
+                .observe(latency);
+        }
+        if let Some(kind) = err {
+            *registry.rpc_errors.entry(kind.to_string()).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn snapshot(&self, node_id: Option<String>, load: ServeStats) -> Snapshot {
+        let registry = self.registry.lock().unwrap();
+        Snapshot {
+            node_id,
+            uptime_ms: self.start.elapsed().as_millis() as u64,
+            sent: registry.sent.clone(),
+            received: registry.received.clone(),
+            rpc_latency: registry.rpc_latency.clone(),
+            rpc_errors: registry.rpc_errors.clone(),
+            errors_sent: registry.errors_sent.clone(),
+            load,
+        }
+    }
+}
+
+/// Metrics for a node at one point in time.
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    /// `None` until the node is initialized.
+    pub node_id: Option<String>,
+    pub uptime_ms: u64,
+    /// Messages sent, by type and destination.
+    pub sent: Counts,
+    /// Messages received, by type and source.
+    pub received: Counts,
+    /// Time to a reply for RPCs, by request type.
+    pub rpc_latency: BTreeMap<String, Histogram>,
+    /// Failed RPCs, by error kind.
+    pub rpc_errors: BTreeMap<String, u64>,
+    /// Error replies sent, by error kind.
+    pub errors_sent: BTreeMap<String, u64>,
+    pub load: ServeStats,
+}
+
+impl Snapshot {
+    /// Total messages sent of one type.
+    pub fn sent_of(&self, ty: &str) -> u64 {
+        self.sent.get(ty).map_or(0, |peers| peers.values().sum())
+    }
+
+    /// Total messages received of one type.
+    pub fn received_of(&self, ty: &str) -> u64 {
+        self.received
+            .get(ty)
+            .map_or(0, |peers| peers.values().sum())
+    }
+}
+
+fn count(counts: &mut Counts, ty: &str, peer: &str) {
+    *counts
+        .entry(ty.to_string())
+        .or_default()
+        .entry(peer.to_string())
+        .or_default() += 1;
+}
+
+fn error_kind(msg: &Message) -> Option<String> {
+    let code = msg.body.extra.get("code")?.as_u64()?;
+    let kind = ErrorKind::from_u8(u8::try_from(code).ok()?)?;
+    Some(kind.to_string())
+}
@@ -0,0 +1,122 @@
+use std::{
+    io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+use crate::{record::JsonlWriter, trace::SpanContext};
+
+/// Environment variable naming the file [`serve`](crate::Node::serve) exports spans to.
+pub const SPANS_ENV: &str = "FLY_DIST_SYS_SPANS";
+
+/// OTLP span kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SpanKind {
+    /// Handling a request.
+    Server = 2,
+    /// Waiting for the reply to an RPC.
+    Client = 3,
+}
+
+/// A request or RPC span that has ended.
+#[derive(Debug, Clone)]
+pub(crate) struct FinishedSpan {
+    pub(crate) ctx: SpanContext,
+    /// The message type.
+    pub(crate) name: String,
+    pub(crate) kind: SpanKind,
+    pub(crate) start: SystemTime,
+    pub(crate) end: SystemTime,
+    /// The node or client on the other end.
+    pub(crate) peer: String,
+    pub(crate) error: Option<String>,
+}
+
+/// Appends finished request and RPC spans to a file in the OTLP JSON encoding, one
+/// `ExportTraceServiceRequest` per line, as the OpenTelemetry Collector's file exporter
+/// writes them.
+///
+/// Each node is its own `service.name`, so all the nodes of a Maelstrom run can share one
+/// file, as with a [`Recorder`](crate::record::Recorder).
+pub struct SpanExporter {
+    writer: JsonlWriter,
+}
+
+impl SpanExporter {
+    /// Append to the file at `path`, creating it if needed.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            writer: JsonlWriter::create(path)?,
+        })
+    }
+
+    /// Create an exporter for the file named by [`SPANS_ENV`], if it is set.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os(SPANS_ENV)?;
+        match Self::create(&path) {
+            Ok(exporter) => Some(exporter),
+            Err(err) => {
+                tracing::error!(?path, %err, "Failed to open span file");
+                None
+            }
+        }
+    }
+
+    pub(crate) fn export(&self, node_id: &str, span: &FinishedSpan) {
+        if let Err(err) = self.writer.write(&encode(node_id, span)) {
+            tracing::error!(%err, "Failed to write span");
+        }
+    }
+}
+
+fn encode(node_id: &str, span: &FinishedSpan) -> Value {
+    let peer_attribute = match span.kind {
+        SpanKind::Server => "msg.src",
+        SpanKind::Client => "msg.dest",
+    };
+    let status = match &span.error {
+        Some(message) => json!({ "code": 2, "message": message }),
+        None => json!({ "code": 0 }),
+    };
+
+    let mut otlp_span = json!({
+        "traceId": span.ctx.trace_id_hex(),
+        "spanId": span.ctx.span_id_hex(),
+        "name": span.name,
+        "kind": span.kind as u8,
+        "startTimeUnixNano": unix_nanos(span.start),
+        "endTimeUnixNano": unix_nanos(span.end),
+        "attributes": [
+            attribute("msg.type", &span.name),
+            attribute(peer_attribute, &span.peer),
+        ],
+        "status": status,
+    });
+    if let Some(parent) = span.ctx.parent_span_id_hex() {
+        otlp_span["parentSpanId"] = parent.into();
+    }
+
+    json!({
+        "resourceSpans": [{
+            "resource": { "attributes": [attribute("service.name", node_id)] },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": [otlp_span],
+            }],
+        }],
+    })
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// OTLP JSON encodes 64-bit integers as strings.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
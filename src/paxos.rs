@@ -0,0 +1,554 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::{
+    error::ErrorKind,
+    proto::{Message, MessageBody},
+    Error, Node,
+};
+
+/// Message type for phase 1 requests from a proposer.
+pub const PAXOS_PREPARE: &str = "paxos_prepare";
+/// Message type for phase 2 requests from a proposer.
+pub const PAXOS_ACCEPT: &str = "paxos_accept";
+/// Message type announcing a decided value to learners.
+pub const PAXOS_DECIDE: &str = "paxos_decide";
+
+/// A proposal number. Ballots are totally ordered by round, then by proposer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Ballot {
+    pub round: u64,
+    pub node_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct PaxosConfig {
+    rpc_timeout: Duration,
+    max_attempts: usize,
+}
+
+impl Default for PaxosConfig {
+    fn default() -> Self {
+        Self {
+            rpc_timeout: Duration::from_millis(500),
+            max_attempts: 16,
+        }
+    }
+}
+
+impl PaxosConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long a proposer waits for acceptors to reply in each phase.
+    pub fn with_rpc_timeout(mut self, timeout: Duration) -> Self {
+        self.rpc_timeout = timeout;
+        self
+    }
+
+    /// Number of ballots a proposer tries before giving up with a `timeout` error.
+    pub fn with_max_attempts(mut self, attempts: usize) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Prepare {
+    instance: u64,
+    ballot: Ballot,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Promise<T> {
+    ok: bool,
+    promised: Ballot,
+    accepted: Option<(Ballot, T)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Accept<T> {
+    instance: u64,
+    ballot: Ballot,
+    value: T,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Accepted {
+    ok: bool,
+    promised: Ballot,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Decide<T> {
+    instance: u64,
+    value: T,
+}
+
+#[derive(Debug, Clone)]
+struct AcceptorInstance<T> {
+    promised: Ballot,
+    accepted: Option<(Ballot, T)>,
+}
+
+impl<T> Default for AcceptorInstance<T> {
+    fn default() -> Self {
+        Self {
+            promised: Ballot::default(),
+            accepted: None,
+        }
+    }
+}
+
+/// Acceptor role: promises to ignore older ballots and accepts proposals.
+pub struct Acceptor<T> {
+    instances: Mutex<HashMap<u64, AcceptorInstance<T>>>,
+}
+
+impl<T> Default for Acceptor<T> {
+    fn default() -> Self {
+        Self {
+            instances: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> Acceptor<T> {
+    /// The highest-ballot proposal accepted for an instance.
+    pub fn accepted(&self, instance: u64) -> Option<(Ballot, T)> {
+        self.instances
+            .lock()
+            .unwrap()
+            .get(&instance)
+            .and_then(|instance| instance.accepted.clone())
+    }
+
+    fn prepare(&self, req: Prepare) -> Promise<T> {
+        let mut instances = self.instances.lock().unwrap();
+        let instance = instances.entry(req.instance).or_default();
+        let ok = req.ballot > instance.promised;
+        if ok {
+            instance.promised = req.ballot;
+        }
+        Promise {
+            ok,
+            promised: instance.promised.clone(),
+            accepted: instance.accepted.clone(),
+        }
+    }
+
+    fn accept(&self, req: Accept<T>) -> Accepted {
+        let mut instances = self.instances.lock().unwrap();
+        let instance = instances.entry(req.instance).or_default();
+        let ok = req.ballot >= instance.promised;
+        if ok {
+            instance.promised = req.ballot.clone();
+            instance.accepted = Some((req.ballot, req.value));
+        }
+        Accepted {
+            ok,
+            promised: instance.promised.clone(),
+        }
+    }
+}
+
+/// Learner role: records decided values.
+pub struct Learner<T> {
+    decided: Mutex<BTreeMap<u64, T>>,
+    notify: Notify,
+}
+
+impl<T> Default for Learner<T> {
+    fn default() -> Self {
+        Self {
+            decided: Mutex::new(BTreeMap::new()),
+            notify: Notify::new(),
+        }
+    }
+}
+
+impl<T: Clone> Learner<T> {
+    fn learn(&self, instance: u64, value: T) {
+        self.decided
+            .lock()
+            .unwrap()
+            .entry(instance)
+            .or_insert(value);
+        self.notify.notify_waiters();
+    }
+
+    /// The decided value of an instance, if known.
+    pub fn get(&self, instance: u64) -> Option<T> {
+        self.decided.lock().unwrap().get(&instance).cloned()
+    }
+
+    /// Wait until an instance is decided.
+    pub async fn wait(&self, instance: u64) -> T {
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if let Some(value) = self.get(instance) {
+                return value;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Lowest instance not known to be decided.
+    pub fn first_undecided(&self) -> u64 {
+        let decided = self.decided.lock().unwrap();
+        let mut instance = 0;
+        while decided.contains_key(&instance) {
+            instance += 1;
+        }
+        instance
+    }
+
+    /// Decided values for the contiguous prefix of instances starting at 0.
+    pub fn prefix(&self) -> Vec<T> {
+        let decided = self.decided.lock().unwrap();
+        (0..)
+            .map_while(|instance| decided.get(&instance).cloned())
+            .collect()
+    }
+}
+
+struct PaxosInner<T> {
+    acceptor: Acceptor<T>,
+    learner: Learner<T>,
+    /// Highest round seen, so new ballots outrank every competing proposer we know of.
+    round: AtomicU64,
+    config: PaxosConfig,
+}
+
+/// Single-decree Paxos instances, with every node acting as proposer, acceptor and learner.
+///
+/// Route `paxos_*` requests to [`handle`](Self::handle) and call
+/// [`propose`](Self::propose) to decide a value.
+pub struct Paxos<T> {
+    inner: Arc<PaxosInner<T>>,
+}
+
+impl<T> Clone for Paxos<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Default for Paxos<T>
+where
+    T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new(PaxosConfig::default())
+    }
+}
+
+impl<T> Paxos<T>
+where
+    T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    pub fn new(config: PaxosConfig) -> Self {
+        Self {
+            inner: Arc::new(PaxosInner {
+                acceptor: Acceptor::default(),
+                learner: Learner::default(),
+                round: AtomicU64::new(0),
+                config,
+            }),
+        }
+    }
+
+    pub fn acceptor(&self) -> &Acceptor<T> {
+        &self.inner.acceptor
+    }
+
+    pub fn learner(&self) -> &Learner<T> {
+        &self.inner.learner
+    }
+
+    /// Handle a Paxos message from a peer. `paxos_decide` messages produce no reply.
+    pub fn handle(&self, req: &Message) -> Result<Option<MessageBody>, Error> {
+        match req.ty() {
+            PAXOS_PREPARE => {
+                let prepare: Prepare = req.body.try_to_message()?;
+                self.observe_round(prepare.ballot.round);
+                let promise = self.inner.acceptor.prepare(prepare);
+                Ok(Some(MessageBody::from_message("paxos_promise", promise)))
+            }
+            PAXOS_ACCEPT => {
+                let accept: Accept<T> = req.body.try_to_message()?;
+                self.observe_round(accept.ballot.round);
+                let accepted = self.inner.acceptor.accept(accept);
+                Ok(Some(MessageBody::from_message("paxos_accepted", accepted)))
+            }
+            PAXOS_DECIDE => {
+                let decide: Decide<T> = req.body.try_to_message()?;
+                self.inner.learner.learn(decide.instance, decide.value);
+                Ok(None)
+            }
+            _ => Err(Error::not_supported()),
+        }
+    }
+
+    /// Try to decide `value` for an instance, returning the value actually decided. This may
+    /// be another proposer's value if one was already chosen.
+    pub async fn propose<S>(&self, node: &Node<S>, instance: u64, value: T) -> Result<T, Error>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let metadata = node.wait_for_init().await;
+        let peers: Vec<String> = metadata
+            .node_ids
+            .iter()
+            .filter(|n| **n != metadata.node_id)
+            .cloned()
+            .collect();
+        let quorum = metadata.node_ids.len() / 2 + 1;
+
+        for _ in 0..self.inner.config.max_attempts {
+            if let Some(value) = self.inner.learner.get(instance) {
+                return Ok(value);
+            }
+
+            let ballot = Ballot {
+                round: self.inner.round.fetch_add(1, Ordering::SeqCst) + 1,
+                node_id: metadata.node_id.clone(),
+            };
+
+            // Phase 1: gather promises, adopting the highest-ballot value already accepted.
+            let prepare = Prepare {
+                instance,
+                ballot: ballot.clone(),
+            };
+            let local = self.inner.acceptor.prepare(prepare.clone());
+            let promises = self
+                .quorum(
+                    node,
+                    &peers,
+                    quorum,
+                    MessageBody::from_message(PAXOS_PREPARE, &prepare),
+                    local,
+                )
+                .await;
+            let Some(promises) = promises else {
+                self.backoff().await;
+                continue;
+            };
+            let value = promises
+                .into_iter()
+                .filter_map(|p: Promise<T>| p.accepted)
+                .max_by(|(a, _), (b, _)| a.cmp(b))
+                .map_or_else(|| value.clone(), |(_, v)| v);
+
+            // Phase 2: ask acceptors to accept the value under our ballot.
+            let accept = Accept {
+                instance,
+                ballot,
+                value: value.clone(),
+            };
+            let local = self.inner.acceptor.accept(accept.clone());
+            let accepted: Option<Vec<Accepted>> = self
+                .quorum(
+                    node,
+                    &peers,
+                    quorum,
+                    MessageBody::from_message(PAXOS_ACCEPT, &accept),
+                    local,
+                )
+                .await;
+            if accepted.is_none() {
+                self.backoff().await;
+                continue;
+            }
+
+            self.inner.learner.learn(instance, value.clone());
+            let decide = MessageBody::from_message(
+                PAXOS_DECIDE,
+                Decide {
+                    instance,
+                    value: &value,
+                },
+            );
+            for peer in &peers {
+                node.send(peer.clone(), decide.clone()).await;
+            }
+            return Ok(value);
+        }
+
+        Err(Error::new(
+            ErrorKind::Timeout,
+            format!("paxos instance {instance} undecided"),
+        ))
+    }
+
+    /// Send a request to every peer and collect replies until a quorum accepts, including
+    /// the local acceptor's reply. Returns `None` if a quorum cannot be reached.
+    async fn quorum<S, R>(
+        &self,
+        node: &Node<S>,
+        peers: &[String],
+        quorum: usize,
+        body: MessageBody,
+        local: R,
+    ) -> Option<Vec<R>>
+    where
+        S: Clone + Send + Sync + 'static,
+        R: Vote + DeserializeOwned,
+    {
+        let timeout = self.inner.config.rpc_timeout;
+        let mut replies: FuturesUnordered<_> = peers
+            .iter()
+            .map(|peer| node.rpc_with_timeout(peer.clone(), body.clone(), timeout))
+            .collect();
+
+        let mut votes = Vec::new();
+        let mut rejected = 0;
+        let mut vote = Some(local);
+
+        loop {
+            if let Some(vote) = vote.take() {
+                self.observe_round(vote.promised().round);
+                if vote.ok() {
+                    votes.push(vote);
+                } else {
+                    rejected += 1;
+                }
+            }
+
+            if votes.len() >= quorum {
+                return Some(votes);
+            }
+            if rejected > peers.len() + 1 - quorum {
+                return None;
+            }
+
+            match replies.next().await {
+                Some(Ok(reply)) => match reply.body.try_to_message::<R>() {
+                    Ok(reply) => vote = Some(reply),
+                    Err(_) => rejected += 1,
+                },
+                Some(Err(_)) => rejected += 1,
+                None => return None,
+            }
+        }
+    }
+
+    fn observe_round(&self, round: u64) {
+        self.inner.round.fetch_max(round, Ordering::SeqCst);
+    }
+
+    /// Wait a random time so duelling proposers stop preempting each other.
+    async fn backoff(&self) {
+        let timeout = self.inner.config.rpc_timeout;
+        let delay = rand::thread_rng().gen_range(Duration::ZERO..timeout / 4);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// An acceptor's reply in either phase.
+trait Vote {
+    fn ok(&self) -> bool;
+    fn promised(&self) -> &Ballot;
+}
+
+impl<T> Vote for Promise<T> {
+    fn ok(&self) -> bool {
+        self.ok
+    }
+
+    fn promised(&self) -> &Ballot {
+        &self.promised
+    }
+}
+
+impl Vote for Accepted {
+    fn ok(&self) -> bool {
+        self.ok
+    }
+
+    fn promised(&self) -> &Ballot {
+        &self.promised
+    }
+}
+
+/// Multi-Paxos replicated log: a sequence of single-decree instances.
+pub struct PaxosLog<T> {
+    paxos: Paxos<T>,
+}
+
+impl<T> Clone for PaxosLog<T> {
+    fn clone(&self) -> Self {
+        Self {
+            paxos: self.paxos.clone(),
+        }
+    }
+}
+
+impl<T> Default for PaxosLog<T>
+where
+    T: Clone + PartialEq + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new(PaxosConfig::default())
+    }
+}
+
+impl<T> PaxosLog<T>
+where
+    T: Clone + PartialEq + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    pub fn new(config: PaxosConfig) -> Self {
+        Self {
+            paxos: Paxos::new(config),
+        }
+    }
+
+    /// Handle a Paxos message from a peer.
+    pub fn handle(&self, req: &Message) -> Result<Option<MessageBody>, Error> {
+        self.paxos.handle(req)
+    }
+
+    /// Append a value to the log, returning the instance it was decided in. Values must be
+    /// unique, since an identical value decided earlier is indistinguishable from this one.
+    pub async fn append<S>(&self, node: &Node<S>, value: T) -> Result<u64, Error>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let mut instance = self.paxos.learner().first_undecided();
+        loop {
+            let decided = self.paxos.propose(node, instance, value.clone()).await?;
+            if decided == value {
+                return Ok(instance);
+            }
+            instance += 1;
+        }
+    }
+
+    /// The decided value at an instance, if known.
+    pub fn get(&self, instance: u64) -> Option<T> {
+        self.paxos.learner().get(instance)
+    }
+
+    /// Every decided value up to the first gap.
+    pub fn entries(&self) -> Vec<T> {
+        self.paxos.learner().prefix()
+    }
+}
@@ -1,7 +1,7 @@
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::Error;
+use crate::{error::ErrorKind, Error};
 
 #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Message {
@@ -45,6 +45,26 @@ impl MessageBody {
         self
     }
 
+    /// Build a body of the given type from the fields of a serializable struct.
+    pub fn from_message(ty: impl Into<String>, msg: impl Serialize) -> Self {
+        let extra = match serde_json::to_value(msg).expect("Failed to serialize value") {
+            Value::Object(extra) => extra,
+            _ => panic!("Message must serialize to an object"),
+        };
+        Self {
+            ty: ty.into(),
+            extra,
+            ..Default::default()
+        }
+    }
+
+    /// Like [`to_message`](Self::to_message), but returns a `malformed_request` error
+    /// instead of panicking.
+    pub fn try_to_message<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        serde_json::from_value(Value::Object(self.extra.clone()))
+            .map_err(|err| Error::new(ErrorKind::MalformedRequest, err.to_string()))
+    }
+
     pub fn to_message<T: DeserializeOwned>(&self) -> T {
         serde_json::from_value(serde_json::to_value(self).expect("Failed to serialize value"))
             .expect("Failed to serialize value")
@@ -66,7 +86,7 @@ where
     T: IntoBody,
 {
     fn into_body(self) -> Option<MessageBody> {
-        self.map(IntoBody::into_body).flatten()
+        self.and_then(IntoBody::into_body)
     }
 }
 
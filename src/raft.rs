@@ -0,0 +1,643 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::oneshot, task::JoinHandle, time::Instant};
+
+use crate::{
+    error::ErrorKind,
+    proto::{Message, MessageBody},
+    Error, Node,
+};
+
+/// Message type for vote requests between candidates and peers.
+pub const REQUEST_VOTE: &str = "request_vote";
+/// Message type for log replication and heartbeats from the leader.
+pub const APPEND_ENTRIES: &str = "append_entries";
+
+/// Field marking a client request that has already been forwarded to the leader.
+const FORWARDED: &str = "raft_forwarded";
+
+/// Maximum number of entries sent in a single `append_entries` message.
+const MAX_BATCH: usize = 128;
+
+/// A deterministic state machine replicated by [`Raft`].
+pub trait StateMachine: Send + 'static {
+    /// Apply a committed client request and produce the reply body.
+    fn apply(&mut self, req: &MessageBody) -> Result<MessageBody, Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    election_timeout: Duration,
+    heartbeat_interval: Duration,
+    rpc_timeout: Duration,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        Self {
+            election_timeout: Duration::from_millis(300),
+            heartbeat_interval: Duration::from_millis(50),
+            rpc_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RaftConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Minimum time without hearing from a leader before starting an election. The actual
+    /// timeout is randomized between this and twice this value.
+    pub fn with_election_timeout(mut self, timeout: Duration) -> Self {
+        self.election_timeout = timeout;
+        self
+    }
+
+    /// Time between heartbeats sent by the leader.
+    pub fn with_heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// How long to wait for peers and the leader to reply.
+    pub fn with_rpc_timeout(mut self, timeout: Duration) -> Self {
+        self.rpc_timeout = timeout;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// A log entry. Leaders append an entry without an operation when elected, so entries from
+/// earlier terms get committed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub term: u64,
+    pub op: Option<MessageBody>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RequestVote {
+    term: u64,
+    candidate_id: String,
+    last_log_index: usize,
+    last_log_term: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RequestVoteOk {
+    term: u64,
+    vote_granted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AppendEntries {
+    term: u64,
+    leader_id: String,
+    prev_log_index: usize,
+    prev_log_term: u64,
+    entries: Vec<Entry>,
+    leader_commit: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AppendEntriesOk {
+    term: u64,
+    success: bool,
+    /// On success, the last index known to match the leader. On failure, the index the
+    /// leader should retry from.
+    match_index: usize,
+}
+
+type Waiter = (u64, oneshot::Sender<Result<MessageBody, Error>>);
+
+struct RaftState<M> {
+    id: String,
+    peers: Vec<String>,
+    role: Role,
+    current_term: u64,
+    voted_for: Option<String>,
+    leader: Option<String>,
+    /// Entries, with a sentinel at index 0 so indices match the Raft paper.
+    log: Vec<Entry>,
+    commit_index: usize,
+    last_applied: usize,
+    next_index: HashMap<String, usize>,
+    match_index: HashMap<String, usize>,
+    votes: HashSet<String>,
+    election_deadline: Instant,
+    machine: M,
+    /// Client requests waiting for their entry to be applied, keyed by log index.
+    waiters: HashMap<usize, Waiter>,
+}
+
+impl<M: StateMachine> RaftState<M> {
+    fn last_log_index(&self) -> usize {
+        self.log.len() - 1
+    }
+
+    fn last_log_term(&self) -> u64 {
+        self.log[self.last_log_index()].term
+    }
+
+    fn majority(&self) -> usize {
+        self.peers.len().div_ceil(2) + 1
+    }
+
+    fn reset_election_deadline(&mut self, timeout: Duration) {
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=timeout);
+        self.election_deadline = Instant::now() + timeout + jitter;
+    }
+
+    /// Adopt a newer term seen in any message, stepping down if needed.
+    fn observe_term(&mut self, term: u64) {
+        if term > self.current_term {
+            self.current_term = term;
+            self.voted_for = None;
+            if self.role != Role::Follower {
+                tracing::info!(term, "Stepping down");
+                self.role = Role::Follower;
+                self.fail_waiters();
+            }
+        }
+    }
+
+    fn become_leader(&mut self) {
+        tracing::info!(term = self.current_term, "Became leader");
+        self.role = Role::Leader;
+        self.leader = Some(self.id.clone());
+        let next = self.last_log_index() + 1;
+        for peer in &self.peers {
+            self.next_index.insert(peer.clone(), next);
+            self.match_index.insert(peer.clone(), 0);
+        }
+        self.log.push(Entry {
+            term: self.current_term,
+            op: None,
+        });
+        self.advance_commit();
+    }
+
+    /// Commit the highest index replicated on a majority, if it is from the current term.
+    fn advance_commit(&mut self) {
+        for n in (self.commit_index + 1..=self.last_log_index()).rev() {
+            if self.log[n].term != self.current_term {
+                break;
+            }
+            let replicas = 1 + self.match_index.values().filter(|&&m| m >= n).count();
+            if replicas >= self.majority() {
+                self.commit_index = n;
+                break;
+            }
+        }
+        self.apply_committed();
+    }
+
+    fn apply_committed(&mut self) {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let entry = &self.log[self.last_applied];
+            let res = entry.op.as_ref().map(|op| self.machine.apply(op));
+
+            if let Some((term, tx)) = self.waiters.remove(&self.last_applied) {
+                let res = match res {
+                    Some(res) if term == entry.term => res,
+                    _ => Err(Error::new(ErrorKind::Crash, "leadership changed")),
+                };
+                let _ = tx.send(res);
+            }
+        }
+    }
+
+    fn append_entries_for(&self, peer: &str) -> AppendEntries {
+        let next = self
+            .next_index
+            .get(peer)
+            .copied()
+            .unwrap_or(1)
+            .clamp(1, self.log.len());
+        let end = self.log.len().min(next + MAX_BATCH);
+        AppendEntries {
+            term: self.current_term,
+            leader_id: self.id.clone(),
+            prev_log_index: next - 1,
+            prev_log_term: self.log[next - 1].term,
+            entries: self.log[next..end].to_vec(),
+            leader_commit: self.commit_index,
+        }
+    }
+
+    fn fail_waiters(&mut self) {
+        for (_, (_, tx)) in self.waiters.drain() {
+            let _ = tx.send(Err(Error::new(ErrorKind::Crash, "leadership changed")));
+        }
+    }
+}
+
+struct RaftInner<M> {
+    state: Mutex<RaftState<M>>,
+    config: RaftConfig,
+}
+
+/// Raft consensus over [`Node::rpc`], replicating client requests into a [`StateMachine`].
+///
+/// Call [`spawn`](Self::spawn) once with the node and route every request to
+/// [`handle`](Self::handle). Requests that are not Raft messages are treated as client
+/// operations: the leader appends them to the log and replies once they are applied, and
+/// followers forward them to the leader.
+pub struct Raft<M> {
+    inner: Arc<RaftInner<M>>,
+}
+
+impl<M> Clone for Raft<M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<M: StateMachine> Raft<M> {
+    pub fn new(machine: M, config: RaftConfig) -> Self {
+        let mut state = RaftState {
+            id: String::new(),
+            peers: Vec::new(),
+            role: Role::Follower,
+            current_term: 0,
+            voted_for: None,
+            leader: None,
+            log: vec![Entry { term: 0, op: None }],
+            commit_index: 0,
+            last_applied: 0,
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            votes: HashSet::new(),
+            election_deadline: Instant::now(),
+            machine,
+            waiters: HashMap::new(),
+        };
+        state.reset_election_deadline(config.election_timeout);
+
+        Self {
+            inner: Arc::new(RaftInner {
+                state: Mutex::new(state),
+                config,
+            }),
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.inner.state.lock().unwrap().role
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role() == Role::Leader
+    }
+
+    /// The leader of the current term, if known.
+    pub fn leader(&self) -> Option<String> {
+        self.inner.state.lock().unwrap().leader.clone()
+    }
+
+    pub fn term(&self) -> u64 {
+        self.inner.state.lock().unwrap().current_term
+    }
+
+    /// Read the state machine. Reads through this are not linearizable; send them through
+    /// the log instead.
+    pub fn read<R>(&self, f: impl FnOnce(&M) -> R) -> R {
+        f(&self.inner.state.lock().unwrap().machine)
+    }
+
+    /// Start the election timer and, when leader, heartbeats.
+    pub fn spawn<S>(&self, node: Node<S>) -> JoinHandle<()>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let raft = self.clone();
+        tokio::spawn(async move {
+            raft.init(&node).await;
+
+            let tick = raft.inner.config.heartbeat_interval / 5;
+            let mut next_heartbeat = Instant::now();
+            loop {
+                tokio::time::sleep(tick).await;
+
+                let (role, deadline) = {
+                    let state = raft.inner.state.lock().unwrap();
+                    (state.role, state.election_deadline)
+                };
+
+                let now = Instant::now();
+                if role == Role::Leader {
+                    if now >= next_heartbeat {
+                        next_heartbeat = now + raft.inner.config.heartbeat_interval;
+                        raft.replicate(&node);
+                    }
+                } else if now >= deadline {
+                    raft.start_election(&node);
+                }
+            }
+        })
+    }
+
+    /// Handle a Raft message or client request.
+    pub async fn handle<S>(&self, node: &Node<S>, req: Message) -> Result<MessageBody, Error>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.init(node).await;
+
+        match req.ty() {
+            REQUEST_VOTE => {
+                let req: RequestVote = req.body.try_to_message()?;
+                let res = self.handle_request_vote(req);
+                Ok(MessageBody::from_message("request_vote_ok", res))
+            }
+            APPEND_ENTRIES => {
+                let req: AppendEntries = req.body.try_to_message()?;
+                let res = self.handle_append_entries(req);
+                Ok(MessageBody::from_message("append_entries_ok", res))
+            }
+            _ => self.propose(node, req).await,
+        }
+    }
+
+    async fn init<S>(&self, node: &Node<S>) {
+        if !self.inner.state.lock().unwrap().id.is_empty() {
+            return;
+        }
+        let metadata = node.wait_for_init().await;
+        let mut state = self.inner.state.lock().unwrap();
+        state.peers = metadata
+            .node_ids
+            .into_iter()
+            .filter(|n| n != &metadata.node_id)
+            .collect();
+        state.id = metadata.node_id;
+    }
+
+    /// Append a client request to the log and wait for it to be applied, or forward it to
+    /// the leader.
+    async fn propose<S>(&self, node: &Node<S>, mut req: Message) -> Result<MessageBody, Error>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let forwarded = req.body.extra.remove(FORWARDED).is_some();
+
+        let (rx, leader) = {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.role == Role::Leader {
+                let term = state.current_term;
+                state.log.push(Entry {
+                    term,
+                    op: Some(req.body.clone()),
+                });
+                let index = state.last_log_index();
+                let (tx, rx) = oneshot::channel();
+                state.waiters.insert(index, (term, tx));
+                state.advance_commit();
+                (Some(rx), None)
+            } else {
+                (None, state.leader.clone())
+            }
+        };
+
+        if let Some(rx) = rx {
+            self.replicate(node);
+            return rx
+                .await
+                .unwrap_or_else(|_| Err(Error::new(ErrorKind::Crash, "leadership changed")));
+        }
+
+        match leader {
+            Some(leader) if !forwarded => {
+                let body = MessageBody {
+                    msg_id: 0,
+                    in_reply_to: 0,
+                    ..req.body
+                }
+                .with_field(FORWARDED, true);
+                let res = node
+                    .rpc_with_timeout(leader, body, self.inner.config.rpc_timeout * 2)
+                    .await?;
+                Ok(MessageBody {
+                    msg_id: 0,
+                    in_reply_to: 0,
+                    ..res.body
+                })
+            }
+            _ => Err(Error::new(
+                ErrorKind::TemporarlilyUnavailable,
+                "no known leader",
+            )),
+        }
+    }
+
+    fn handle_request_vote(&self, req: RequestVote) -> RequestVoteOk {
+        let mut state = self.inner.state.lock().unwrap();
+        state.observe_term(req.term);
+
+        let up_to_date = (req.last_log_term, req.last_log_index)
+            >= (state.last_log_term(), state.last_log_index());
+        let can_vote = state
+            .voted_for
+            .as_ref()
+            .is_none_or(|v| v == &req.candidate_id);
+
+        let vote_granted = req.term == state.current_term && can_vote && up_to_date;
+        if vote_granted {
+            state.voted_for = Some(req.candidate_id);
+            state.reset_election_deadline(self.inner.config.election_timeout);
+        }
+
+        RequestVoteOk {
+            term: state.current_term,
+            vote_granted,
+        }
+    }
+
+    fn handle_append_entries(&self, req: AppendEntries) -> AppendEntriesOk {
+        let mut state = self.inner.state.lock().unwrap();
+        state.observe_term(req.term);
+
+        if req.term < state.current_term {
+            return AppendEntriesOk {
+                term: state.current_term,
+                success: false,
+                match_index: 0,
+            };
+        }
+
+        if state.role != Role::Follower {
+            state.role = Role::Follower;
+            state.fail_waiters();
+        }
+        state.leader = Some(req.leader_id);
+        state.reset_election_deadline(self.inner.config.election_timeout);
+
+        if req.prev_log_index > state.last_log_index() {
+            return AppendEntriesOk {
+                term: state.current_term,
+                success: false,
+                match_index: state.last_log_index() + 1,
+            };
+        }
+        if state.log[req.prev_log_index].term != req.prev_log_term {
+            return AppendEntriesOk {
+                term: state.current_term,
+                success: false,
+                match_index: req.prev_log_index.max(1),
+            };
+        }
+
+        // Only entries up to the last one the leader sent are known to match its log; any
+        // after that may still be overwritten.
+        let match_index = req.prev_log_index + req.entries.len();
+        for (i, entry) in req.entries.into_iter().enumerate() {
+            let index = req.prev_log_index + 1 + i;
+            if index <= state.last_log_index() {
+                if state.log[index].term == entry.term {
+                    continue;
+                }
+                state.log.truncate(index);
+            }
+            state.log.push(entry);
+        }
+
+        if req.leader_commit > state.commit_index {
+            state.commit_index = req.leader_commit.min(match_index);
+            state.apply_committed();
+        }
+
+        AppendEntriesOk {
+            term: state.current_term,
+            success: true,
+            match_index,
+        }
+    }
+
+    fn start_election<S>(&self, node: &Node<S>)
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let (req, peers) = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.current_term += 1;
+            state.role = Role::Candidate;
+            state.leader = None;
+            state.voted_for = Some(state.id.clone());
+            state.votes = HashSet::from([state.id.clone()]);
+            state.reset_election_deadline(self.inner.config.election_timeout);
+            state.fail_waiters();
+            tracing::info!(term = state.current_term, "Starting election");
+
+            if state.votes.len() >= state.majority() {
+                state.become_leader();
+                return;
+            }
+
+            let req = RequestVote {
+                term: state.current_term,
+                candidate_id: state.id.clone(),
+                last_log_index: state.last_log_index(),
+                last_log_term: state.last_log_term(),
+            };
+            (req, state.peers.clone())
+        };
+
+        for peer in peers {
+            let raft = self.clone();
+            let node = node.clone();
+            let req = req.clone();
+            tokio::spawn(async move {
+                let res = node
+                    .rpc_with_timeout(
+                        peer.clone(),
+                        MessageBody::from_message(REQUEST_VOTE, &req),
+                        raft.inner.config.rpc_timeout,
+                    )
+                    .await;
+                let Ok(res) = res.and_then(|res| res.body.try_to_message::<RequestVoteOk>()) else {
+                    return;
+                };
+
+                let mut state = raft.inner.state.lock().unwrap();
+                state.observe_term(res.term);
+                if state.role == Role::Candidate
+                    && state.current_term == req.term
+                    && res.vote_granted
+                {
+                    state.votes.insert(peer);
+                    if state.votes.len() >= state.majority() {
+                        state.become_leader();
+                    }
+                }
+            });
+        }
+    }
+
+    /// Send every peer the entries it is missing, or a heartbeat if it has them all.
+    fn replicate<S>(&self, node: &Node<S>)
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let requests: Vec<(String, AppendEntries)> = {
+            let state = self.inner.state.lock().unwrap();
+            if state.role != Role::Leader {
+                return;
+            }
+            state
+                .peers
+                .iter()
+                .map(|peer| (peer.clone(), state.append_entries_for(peer)))
+                .collect()
+        };
+
+        for (peer, req) in requests {
+            let raft = self.clone();
+            let node = node.clone();
+            tokio::spawn(async move {
+                let res = node
+                    .rpc_with_timeout(
+                        peer.clone(),
+                        MessageBody::from_message(APPEND_ENTRIES, &req),
+                        raft.inner.config.rpc_timeout,
+                    )
+                    .await;
+                let Ok(res) = res.and_then(|res| res.body.try_to_message::<AppendEntriesOk>())
+                else {
+                    return;
+                };
+
+                let mut state = raft.inner.state.lock().unwrap();
+                state.observe_term(res.term);
+                if state.role != Role::Leader || state.current_term != req.term {
+                    return;
+                }
+
+                if res.success {
+                    let matched = state.match_index.entry(peer.clone()).or_default();
+                    *matched = (*matched).max(res.match_index);
+                    let matched = *matched;
+                    state.next_index.insert(peer, matched + 1);
+                    state.advance_commit();
+                } else {
+                    state.next_index.insert(peer, res.match_index.max(1));
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::proto::Message;
+
+/// Environment variable naming the file [`serve`](crate::Node::serve) records messages to.
+pub const TRACE_ENV: &str = "FLY_DIST_SYS_TRACE";
+
+/// Whether a message was received or sent by the recording node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// One line of a trace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub direction: Direction,
+    /// Microseconds since the Unix epoch.
+    pub wall: u64,
+    /// Microseconds since the recorder was created. Only comparable within one process.
+    pub mono: u64,
+    pub msg: Message,
+}
+
+/// An append-only file of JSON values, one per line.
+///
+/// Each line is written with a single append, so several processes can share one file;
+/// this is how all the nodes of a Maelstrom run end up writing one trace for the whole
+/// cluster.
+pub(crate) struct JsonlWriter {
+    file: Mutex<File>,
+}
+
+impl JsonlWriter {
+    /// Append to the file at `path`, creating it if needed.
+    pub(crate) fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub(crate) fn write(&self, value: &impl Serialize) -> io::Result<()> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        self.file.lock().unwrap().write_all(&line)
+    }
+}
+
+/// Appends every message a node receives or sends to a JSONL file of [`Record`]s, which
+/// the nodes of a cluster can share.
+pub struct Recorder {
+    writer: JsonlWriter,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Append to the file at `path`, creating it if needed.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            writer: JsonlWriter::create(path)?,
+            start: Instant::now(),
+        })
+    }
+
+    /// Create a recorder for the file named by [`TRACE_ENV`], if it is set.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os(TRACE_ENV)?;
+        match Self::create(&path) {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                tracing::error!(?path, %err, "Failed to open trace file");
+                None
+            }
+        }
+    }
+
+    pub fn record(&self, direction: Direction, msg: &Message) {
+        let record = Record {
+            direction,
+            wall: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64,
+            mono: self.start.elapsed().as_micros() as u64,
+            msg: msg.clone(),
+        };
+
+        if let Err(err) = self.writer.write(&record) {
+            tracing::error!(%err, "Failed to write trace record");
+        }
+    }
+}
+
+/// Read a trace written by a [`Recorder`], skipping lines that do not parse.
+pub fn read_trace(path: impl AsRef<Path>) -> io::Result<Vec<Record>> {
+    let trace = std::fs::read_to_string(path)?;
+    Ok(trace
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
@@ -0,0 +1,241 @@
+use std::{collections::HashSet, fmt, future::Future, io, path::Path, time::Duration};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    proto::{IntoBody, Message},
+    record::{read_trace, Direction, Record},
+    serve::ServeConfig,
+    trace::strip,
+    Node,
+};
+
+/// Environment variable naming a trace for [`serve`](crate::Node::serve) to replay instead
+/// of reading stdin.
+pub const REPLAY_ENV: &str = "FLY_DIST_SYS_REPLAY";
+
+/// How long to wait for more output once every message has been fed to the node.
+const DEFAULT_SETTLE: Duration = Duration::from_millis(200);
+
+/// Feeds the messages a node received in a recorded trace back into a handler, and compares
+/// what it sends with what it sent in the recording.
+///
+/// Replies to the node's own RPCs are replayed too, each held until the node has sent the
+/// request it answers. They only line up with those requests if the handler assigns message
+/// ids in the same order as it did when recorded.
+pub struct Replay {
+    records: Vec<Record>,
+    node_id: Option<String>,
+    timing: bool,
+    settle: Duration,
+}
+
+impl Replay {
+    pub fn new(records: Vec<Record>) -> Self {
+        Self {
+            records,
+            node_id: None,
+            timing: false,
+            settle: DEFAULT_SETTLE,
+        }
+    }
+
+    /// Load a trace written by a [`Recorder`](crate::record::Recorder).
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(read_trace(path)?))
+    }
+
+    /// Replay the messages of this node. Defaults to the node of the first `init` in the
+    /// trace, which matters when several nodes recorded to the same file.
+    pub fn with_node(mut self, node_id: impl Into<String>) -> Self {
+        self.node_id = Some(node_id.into());
+        self
+    }
+
+    /// Wait between messages as long as the node did when they were recorded.
+    pub fn with_timing(mut self, timing: bool) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// How long to wait for more output once every message has been fed to the node.
+    pub fn with_settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// Replay into a fresh node with the default [`ServeConfig`].
+    pub async fn run<S, F, Fut, B>(self, node: &Node<S>, f: F) -> ReplayReport
+    where
+        S: Clone + Send + Sync + 'static,
+        F: Fn(Node<S>, Message) -> Fut + Copy + Send + Sync + 'static,
+        Fut: Future<Output = B> + Send + 'static,
+        B: IntoBody + 'static,
+    {
+        self.run_with(node, ServeConfig::default(), f).await
+    }
+
+    /// Replay into a fresh node, scheduling handlers according to `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is already being served.
+    pub async fn run_with<S, F, Fut, B>(
+        self,
+        node: &Node<S>,
+        config: ServeConfig,
+        f: F,
+    ) -> ReplayReport
+    where
+        S: Clone + Send + Sync + 'static,
+        F: Fn(Node<S>, Message) -> Fut + Copy + Send + Sync + 'static,
+        Fut: Future<Output = B> + Send + 'static,
+        B: IntoBody + 'static,
+    {
+        let node_id = self.node_id.clone().or_else(|| {
+            self.records
+                .iter()
+                .find(|record| record.direction == Direction::In && record.msg.ty() == "init")
+                .map(|record| record.msg.dst.clone())
+        });
+        let is_node = |record: &&Record| match (&node_id, record.direction) {
+            (None, _) => true,
+            (Some(id), Direction::In) => &record.msg.dst == id,
+            (Some(id), Direction::Out) => &record.msg.src == id,
+        };
+
+        let (inputs, expected): (Vec<&Record>, Vec<&Record>) = self
+            .records
+            .iter()
+            .filter(is_node)
+            .partition(|record| record.direction == Direction::In);
+
+        let (output_tx, mut output_rx) = mpsc::unbounded_channel();
+        let (input_tx, input_rx) = mpsc::unbounded_channel();
+        node.set_output(output_tx);
+        let server = tokio::spawn({
+            let node = node.clone();
+            async move { node.serve_channel(config, input_rx, f).await }
+        });
+
+        let mut actual: Vec<Message> = Vec::new();
+        let mut sent = HashSet::new();
+        let mut last = None;
+        for record in inputs {
+            if let (true, Some(last)) = (self.timing, last) {
+                let delay = record.mono.saturating_sub(last);
+                tokio::time::sleep(Duration::from_micros(delay)).await;
+            }
+            last = Some(record.mono);
+
+            // A reply that arrives before its RPC is registered would be dropped.
+            let in_reply_to = record.msg.body.in_reply_to;
+            while in_reply_to != 0 && !sent.contains(&in_reply_to) {
+                match tokio::time::timeout(self.settle, output_rx.recv()).await {
+                    Ok(Some(msg)) => {
+                        sent.insert(msg.body.msg_id);
+                        actual.push(msg);
+                    }
+                    _ => break,
+                }
+            }
+            let _ = input_tx.send(record.msg.clone());
+        }
+
+        while let Ok(Some(msg)) = tokio::time::timeout(self.settle, output_rx.recv()).await {
+            actual.push(msg);
+        }
+        drop(input_tx);
+        server.abort();
+
+        let expected = expected.into_iter().map(|record| record.msg.clone());
+        ReplayReport::new(expected.collect(), actual)
+    }
+}
+
+/// A difference between the recorded and replayed output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diff {
+    /// A recorded message the replay did not send.
+    Missing(Message),
+    /// A message the replay sent that was not recorded.
+    Unexpected(Message),
+    /// A reply to the same request with a different body.
+    Changed { expected: Message, actual: Message },
+}
+
+/// Output of a [`Replay`]. Messages are matched regardless of order, since handlers run
+/// concurrently, and of trace ids, which are random.
+#[derive(Debug, Clone)]
+pub struct ReplayReport {
+    pub outputs: Vec<Message>,
+    pub diffs: Vec<Diff>,
+}
+
+impl ReplayReport {
+    fn new(expected: Vec<Message>, outputs: Vec<Message>) -> Self {
+        let mut unmatched = outputs.clone();
+        let mut missing = Vec::new();
+        for msg in expected {
+            match unmatched
+                .iter()
+                .position(|actual| strip(actual) == strip(&msg))
+            {
+                Some(i) => {
+                    unmatched.remove(i);
+                }
+                None => missing.push(msg),
+            }
+        }
+
+        let mut diffs = Vec::new();
+        for expected in missing {
+            let changed = unmatched.iter().position(|actual| {
+                expected.body.in_reply_to != 0
+                    && actual.dst == expected.dst
+                    && actual.body.in_reply_to == expected.body.in_reply_to
+            });
+            match changed {
+                Some(i) => diffs.push(Diff::Changed {
+                    expected,
+                    actual: unmatched.remove(i),
+                }),
+                None => diffs.push(Diff::Missing(expected)),
+            }
+        }
+        diffs.extend(unmatched.into_iter().map(Diff::Unexpected));
+
+        Self { outputs, diffs }
+    }
+
+    /// Whether the replay sent exactly the recorded messages.
+    pub fn is_ok(&self) -> bool {
+        self.diffs.is_empty()
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} messages sent, {} difference(s)",
+            self.outputs.len(),
+            self.diffs.len()
+        )?;
+        for diff in &self.diffs {
+            match diff {
+                Diff::Missing(msg) => writeln!(f, "- {}", json(msg))?,
+                Diff::Unexpected(msg) => writeln!(f, "+ {}", json(msg))?,
+                Diff::Changed { expected, actual } => {
+                    writeln!(f, "- {}", json(expected))?;
+                    writeln!(f, "+ {}", json(actual))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn json(msg: &Message) -> String {
+    serde_json::to_string(msg).unwrap()
+}
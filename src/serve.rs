@@ -0,0 +1,317 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing_subscriber::EnvFilter;
+
+use crate::proto::Message;
+
+/// Function extracting an ordering key from a request.
+pub type KeyFn = Arc<dyn Fn(&Message) -> Option<String> + Send + Sync>;
+
+/// How incoming requests are scheduled onto handler tasks.
+#[derive(Clone, Default)]
+pub enum Dispatch {
+    /// Every request is handled in its own task with no ordering guarantees.
+    #[default]
+    Concurrent,
+    /// Requests from the same `src` are handled one at a time, in the order they were received.
+    /// Requests from different sources still run concurrently.
+    PerSource,
+    /// Requests mapping to the same key are handled one at a time, in the order they were
+    /// received. Requests for which the function returns `None` are handled concurrently.
+    PerKey(KeyFn),
+}
+
+impl Dispatch {
+    /// Serialize requests by a key extracted from each message.
+    pub fn per_key<F>(f: F) -> Self
+    where
+        F: Fn(&Message) -> Option<String> + Send + Sync + 'static,
+    {
+        Self::PerKey(Arc::new(f))
+    }
+
+    /// The ordering key for a request, or `None` if it can run concurrently.
+    pub(crate) fn key(&self, msg: &Message) -> Option<String> {
+        match self {
+            Dispatch::Concurrent => None,
+            Dispatch::PerSource => Some(msg.src.clone()),
+            Dispatch::PerKey(f) => f(msg),
+        }
+    }
+}
+
+impl fmt::Debug for Dispatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dispatch::Concurrent => write!(f, "Concurrent"),
+            Dispatch::PerSource => write!(f, "PerSource"),
+            Dispatch::PerKey(_) => write!(f, "PerKey(..)"),
+        }
+    }
+}
+
+/// What to do with a request that arrives while the node is saturated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overload {
+    /// Reply with a `temporarily_unavailable` error.
+    #[default]
+    Reject,
+    /// Drop the request without replying.
+    Drop,
+}
+
+/// How [`Node::serve_with`](crate::Node::serve_with) sets up logging.
+///
+/// The built-in formats write to stderr, since stdout carries protocol messages, and filter
+/// with `RUST_LOG`, defaulting to `info`. Nothing is installed if the program already set a
+/// global subscriber.
+#[derive(Clone, Debug, Default)]
+pub enum Logging {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+    /// Install this subscriber instead.
+    Custom(tracing::Dispatch),
+    /// Leave logging alone, for programs that set up their own subscriber or want none.
+    Disabled,
+}
+
+impl Logging {
+    /// Install the subscriber globally, unless one already is.
+    pub(crate) fn init(&self) {
+        let filter =
+            || EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+        let fmt = || {
+            tracing_subscriber::fmt()
+                .with_ansi(false)
+                .with_writer(std::io::stderr)
+                .with_env_filter(filter())
+        };
+
+        let res = match self {
+            Logging::Text => fmt().try_init(),
+            Logging::Json => fmt().json().try_init(),
+            Logging::Custom(dispatch) => {
+                tracing::dispatcher::set_global_default(dispatch.clone()).map_err(Into::into)
+            }
+            Logging::Disabled => Ok(()),
+        };
+        if let Err(err) = res {
+            tracing::debug!(%err, "Keeping existing subscriber");
+        }
+    }
+}
+
+/// Configuration for [`Node::serve_with`](crate::Node::serve_with).
+#[derive(Clone, Debug, Default)]
+pub struct ServeConfig {
+    pub(crate) dispatch: Dispatch,
+    pub(crate) max_in_flight: Option<usize>,
+    pub(crate) max_queued: Option<usize>,
+    pub(crate) overload: Overload,
+    pub(crate) logging: Logging,
+}
+
+impl ServeConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how requests are scheduled onto handler tasks.
+    pub fn with_dispatch(mut self, dispatch: Dispatch) -> Self {
+        self.dispatch = dispatch;
+        self
+    }
+
+    /// Limit the number of handlers running at once. Requests over the limit wait in a queue,
+    /// which holds as many requests as the limit unless set with
+    /// [`with_max_queued`](Self::with_max_queued).
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight);
+        self
+    }
+
+    /// Limit the number of requests waiting for a handler slot. Once the queue is full, new
+    /// requests are handled according to the [`Overload`] policy. Has no effect without
+    /// [`with_max_in_flight`](Self::with_max_in_flight).
+    pub fn with_max_queued(mut self, max_queued: usize) -> Self {
+        self.max_queued = Some(max_queued);
+        self
+    }
+
+    /// Set what happens to requests that arrive once the queue is full.
+    pub fn with_overload(mut self, overload: Overload) -> Self {
+        self.overload = overload;
+        self
+    }
+
+    /// Set how logging is initialized.
+    pub fn with_logging(mut self, logging: Logging) -> Self {
+        self.logging = logging;
+        self
+    }
+}
+
+/// A snapshot of the request load on a node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ServeStats {
+    /// Handlers currently running.
+    pub in_flight: usize,
+    /// Requests admitted but waiting for a handler slot.
+    pub queued: usize,
+    /// Requests turned away because the node was saturated.
+    pub rejected: u64,
+}
+
+/// Load counters shared between a node and its serve loop.
+#[derive(Debug, Default)]
+pub(crate) struct LoadCounters {
+    pending: AtomicUsize,
+    in_flight: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+impl LoadCounters {
+    pub(crate) fn stats(&self) -> ServeStats {
+        let in_flight = self.in_flight.load(Ordering::SeqCst);
+        let pending = self.pending.load(Ordering::SeqCst);
+        ServeStats {
+            in_flight,
+            queued: pending.saturating_sub(in_flight),
+            rejected: self.rejected.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Admission control for request handlers.
+pub(crate) struct Limiter {
+    counters: Arc<LoadCounters>,
+    permits: Option<Arc<Semaphore>>,
+    capacity: Option<usize>,
+}
+
+impl Limiter {
+    pub(crate) fn new(config: &ServeConfig, counters: Arc<LoadCounters>) -> Self {
+        let capacity = config
+            .max_in_flight
+            .map(|in_flight| in_flight + config.max_queued.unwrap_or(in_flight));
+        Self {
+            counters,
+            permits: config.max_in_flight.map(|n| Arc::new(Semaphore::new(n))),
+            capacity,
+        }
+    }
+
+    /// Admit a request, or return `None` if the node is saturated.
+    pub(crate) fn admit(&self) -> Option<Admission> {
+        let pending = &self.counters.pending;
+        let admitted = match self.capacity {
+            Some(capacity) => pending
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                    (n < capacity).then_some(n + 1)
+                })
+                .is_ok(),
+            None => {
+                pending.fetch_add(1, Ordering::SeqCst);
+                true
+            }
+        };
+
+        if admitted {
+            Some(Admission {
+                counters: self.counters.clone(),
+                permits: self.permits.clone(),
+            })
+        } else {
+            self.counters.rejected.fetch_add(1, Ordering::SeqCst);
+            None
+        }
+    }
+}
+
+/// An admitted request. Dropping it releases its place in the queue.
+pub(crate) struct Admission {
+    counters: Arc<LoadCounters>,
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl Admission {
+    /// Wait for a handler slot.
+    pub(crate) async fn run(&self) -> Running {
+        let permit = match &self.permits {
+            Some(permits) => Some(permits.clone().acquire_owned().await.unwrap()),
+            None => None,
+        };
+        self.counters.in_flight.fetch_add(1, Ordering::SeqCst);
+        Running {
+            counters: self.counters.clone(),
+            _permit: permit,
+        }
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.counters.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A running handler. Dropping it frees its slot.
+pub(crate) struct Running {
+    counters: Arc<LoadCounters>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.counters.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Pending requests for every key that currently has a worker draining it.
+#[derive(Default)]
+pub(crate) struct KeyedQueues {
+    queues: std::sync::Mutex<HashMap<String, VecDeque<(Message, Admission)>>>,
+}
+
+impl KeyedQueues {
+    /// Queue a request, returning `true` if no worker is draining the key yet.
+    pub(crate) fn push(&self, key: &str, msg: (Message, Admission)) -> bool {
+        let mut queues = self.queues.lock().unwrap();
+        match queues.get_mut(key) {
+            Some(queue) => {
+                queue.push_back(msg);
+                false
+            }
+            None => {
+                queues.insert(key.to_string(), VecDeque::from([msg]));
+                true
+            }
+        }
+    }
+
+    /// Take the next request for a key. Once the queue is empty the key is removed, so the
+    /// next [`push`](Self::push) starts a new worker.
+    pub(crate) fn pop(&self, key: &str) -> Option<(Message, Admission)> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.get_mut(key)?;
+        match queue.pop_front() {
+            Some(msg) => Some(msg),
+            None => {
+                queues.remove(key);
+                None
+            }
+        }
+    }
+}
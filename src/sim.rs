@@ -0,0 +1,512 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::future::try_join_all;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+use crate::{
+    kv::{LIN_KV, LWW_KV, SEQ_KV},
+    proto::{IntoBody, Message, MessageBody},
+    serve::ServeConfig,
+    tso::LIN_TSO,
+    Error, Node,
+};
+
+/// Client id used for requests sent through [`Sim::rpc`].
+const CLIENT: &str = "c1";
+
+/// How long [`Sim::rpc`] waits for a reply.
+const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of past versions [`SeqKv`] keeps for stale reads.
+const SEQ_KV_HISTORY: usize = 32;
+
+/// Number of replicas backing [`LwwKv`].
+const LWW_KV_REPLICAS: usize = 3;
+
+/// A service node, such as `lin-kv`, that answers every request synchronously.
+pub trait Service: Send + 'static {
+    fn handle(&mut self, req: &Message) -> Result<MessageBody, Error>;
+}
+
+type Routes = Arc<std::sync::Mutex<HashMap<String, mpsc::UnboundedSender<Message>>>>;
+type Clients = Arc<Mutex<HashMap<u32, oneshot::Sender<Message>>>>;
+/// Links that drop every message, as `(src, dest)` pairs.
+type Cuts = Arc<std::sync::Mutex<HashSet<(String, String)>>>;
+
+/// An in-process network that runs nodes and services without Maelstrom.
+///
+/// Messages between nodes, services and the test client are routed by `dest` over
+/// channels, so handlers run exactly as they would under `serve`. Links can be cut with
+/// [`partition`](Self::partition) to test how nodes cope with lost messages.
+pub struct Sim {
+    network: mpsc::UnboundedSender<Message>,
+    routes: Routes,
+    clients: Clients,
+    cuts: Cuts,
+    node_ids: Vec<String>,
+    msg_ctr: AtomicU32,
+}
+
+impl Default for Sim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sim {
+    /// Create an empty network. Must be called from within a Tokio runtime.
+    pub fn new() -> Self {
+        let (network, mut rx) = mpsc::unbounded_channel::<Message>();
+        let routes = Routes::default();
+        let clients = Clients::default();
+        let cuts = Cuts::default();
+
+        tokio::spawn({
+            let routes = routes.clone();
+            let clients = clients.clone();
+            let cuts = cuts.clone();
+            async move {
+                while let Some(msg) = rx.recv().await {
+                    let link = (msg.src.clone(), msg.dst.clone());
+                    if cuts.lock().unwrap().contains(&link) {
+                        tracing::debug!(?msg, "Dropping message on a cut link");
+                        continue;
+                    }
+
+                    let route = routes.lock().unwrap().get(&msg.dst).cloned();
+                    if let Some(route) = route {
+                        let _ = route.send(msg);
+                    } else if let Some(tx) = clients.lock().await.remove(&msg.body.in_reply_to) {
+                        let _ = tx.send(msg);
+                    } else {
+                        tracing::debug!(?msg, "Dropping undeliverable message");
+                    }
+                }
+            }
+        });
+
+        Self {
+            network,
+            routes,
+            clients,
+            cuts,
+            node_ids: Vec::new(),
+            msg_ctr: AtomicU32::new(1),
+        }
+    }
+
+    /// Create a network with in-memory `lin-kv`, `seq-kv`, `lww-kv` and `lin-tso` services.
+    pub fn with_kv_services() -> Self {
+        let mut sim = Self::new();
+        sim.add_service(LIN_KV, LinKv::new());
+        sim.add_service(SEQ_KV, SeqKv::new());
+        sim.add_service(LWW_KV, LwwKv::new());
+        sim.add_service(LIN_TSO, LinTso::new());
+        sim
+    }
+
+    /// Ids of the nodes added so far.
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// Add a node served with the default [`ServeConfig`].
+    pub fn add_node<S, F, Fut, B>(&mut self, node_id: impl Into<String>, node: &Node<S>, f: F)
+    where
+        S: Clone + Send + Sync + 'static,
+        F: Fn(Node<S>, Message) -> Fut + Copy + Send + Sync + 'static,
+        Fut: Future<Output = B> + Send + 'static,
+        B: IntoBody + 'static,
+    {
+        self.add_node_with(node_id, node, ServeConfig::default(), f)
+    }
+
+    /// Add a node served with `config`.
+    pub fn add_node_with<S, F, Fut, B>(
+        &mut self,
+        node_id: impl Into<String>,
+        node: &Node<S>,
+        config: ServeConfig,
+        f: F,
+    ) where
+        S: Clone + Send + Sync + 'static,
+        F: Fn(Node<S>, Message) -> Fut + Copy + Send + Sync + 'static,
+        Fut: Future<Output = B> + Send + 'static,
+        B: IntoBody + 'static,
+    {
+        let node_id = node_id.into();
+        let rx = self.route(&node_id);
+        node.set_output(self.network.clone());
+        self.node_ids.push(node_id);
+
+        let node = node.clone();
+        tokio::spawn(async move { node.serve_channel(config, rx, f).await });
+    }
+
+    /// Add a service node, such as one of the key-value stores.
+    pub fn add_service(&mut self, name: impl Into<String>, mut service: impl Service) {
+        let name = name.into();
+        let mut rx = self.route(&name);
+        let network = self.network.clone();
+
+        tokio::spawn(async move {
+            while let Some(req) = rx.recv().await {
+                let mut body = service.handle(&req).unwrap_or_else(Into::into);
+                body.in_reply_to = req.body.msg_id;
+                let _ = network.send(Message {
+                    src: name.clone(),
+                    dst: req.src,
+                    body,
+                });
+            }
+        });
+    }
+
+    fn route(&self, id: &str) -> mpsc::UnboundedReceiver<Message> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.routes.lock().unwrap().insert(id.to_string(), tx);
+        rx
+    }
+
+    /// Drop every message sent from `from` to `to`, leaving the other direction intact.
+    pub fn disconnect(&self, from: &str, to: &str) {
+        let link = (from.to_string(), to.to_string());
+        self.cuts.lock().unwrap().insert(link);
+    }
+
+    /// Drop every message between a node in `a` and a node in `b`, in both directions.
+    pub fn partition(&self, a: &[&str], b: &[&str]) {
+        for from in a {
+            for to in b {
+                self.disconnect(from, to);
+                self.disconnect(to, from);
+            }
+        }
+    }
+
+    /// Restore every link cut by [`disconnect`](Self::disconnect) or
+    /// [`partition`](Self::partition).
+    pub fn heal(&self) {
+        self.cuts.lock().unwrap().clear();
+    }
+
+    /// Send `init` to every node and wait for them to acknowledge it.
+    pub async fn init(&self) -> Result<(), Error> {
+        try_join_all(self.node_ids.iter().map(|node_id| {
+            self.rpc(
+                node_id,
+                MessageBody::new("init")
+                    .with_field("node_id", node_id)
+                    .with_field("node_ids", &self.node_ids),
+            )
+        }))
+        .await?;
+        Ok(())
+    }
+
+    /// Send a request from a client and wait for the reply.
+    pub async fn rpc(&self, dst: &str, body: MessageBody) -> Result<Message, Error> {
+        let msg_id = self.msg_ctr.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = oneshot::channel();
+        self.clients.lock().await.insert(msg_id, tx);
+
+        let _ = self.network.send(Message {
+            src: CLIENT.to_string(),
+            dst: dst.to_string(),
+            body: MessageBody { msg_id, ..body },
+        });
+
+        match tokio::time::timeout(RPC_TIMEOUT, rx).await {
+            Ok(Ok(res)) if res.ty() == "error" => Err(Error::from(res.body)),
+            Ok(Ok(res)) => Ok(res),
+            _ => {
+                self.clients.lock().await.remove(&msg_id);
+                Err(Error::timeout())
+            }
+        }
+    }
+}
+
+/// A request to one of the key-value services.
+enum KvOp {
+    Read {
+        key: String,
+    },
+    Write {
+        key: String,
+        value: Value,
+    },
+    Cas {
+        key: String,
+        from: Value,
+        to: Value,
+        create_if_not_exists: bool,
+    },
+}
+
+impl KvOp {
+    fn parse(req: &Message) -> Result<Self, Error> {
+        let field = |name: &str| {
+            req.body
+                .extra
+                .get(name)
+                .cloned()
+                .ok_or_else(Error::malformed_request)
+        };
+        // Keys may be any JSON value, so store them by their encoding.
+        let key = field("key")?.to_string();
+
+        match req.ty() {
+            "read" => Ok(Self::Read { key }),
+            "write" => Ok(Self::Write {
+                key,
+                value: field("value")?,
+            }),
+            "cas" => Ok(Self::Cas {
+                key,
+                from: field("from")?,
+                to: field("to")?,
+                create_if_not_exists: field("create_if_not_exists")
+                    .ok()
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or_default(),
+            }),
+            _ => Err(Error::not_supported()),
+        }
+    }
+
+    fn key(&self) -> &str {
+        match self {
+            KvOp::Read { key } | KvOp::Write { key, .. } | KvOp::Cas { key, .. } => key,
+        }
+    }
+
+    /// Apply the operation to a single copy of the data.
+    fn apply(self, data: &mut HashMap<String, Value>) -> Result<MessageBody, Error> {
+        match self {
+            KvOp::Read { key } => {
+                let value = data.get(&key).ok_or_else(Error::key_does_not_exist)?;
+                Ok(MessageBody::new("read_ok").with_field("value", value))
+            }
+            KvOp::Write { key, value } => {
+                data.insert(key, value);
+                Ok(MessageBody::new("write_ok"))
+            }
+            KvOp::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => {
+                match data.get(&key) {
+                    Some(current) if *current != from => return Err(Error::precondition_failed()),
+                    None if !create_if_not_exists => return Err(Error::key_does_not_exist()),
+                    _ => {}
+                }
+                data.insert(key, to);
+                Ok(MessageBody::new("cas_ok"))
+            }
+        }
+    }
+}
+
+/// Linearizable key-value store: every operation sees the latest state.
+#[derive(Debug, Default)]
+pub struct LinKv {
+    data: HashMap<String, Value>,
+}
+
+impl LinKv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Service for LinKv {
+    fn handle(&mut self, req: &Message) -> Result<MessageBody, Error> {
+        KvOp::parse(req)?.apply(&mut self.data)
+    }
+}
+
+/// Sequentially consistent key-value store.
+///
+/// Writes apply to the latest state, but reads may return any state at least as recent as
+/// the last one the same client observed, so other clients' writes can be missed for a while.
+#[derive(Debug)]
+pub struct SeqKv {
+    /// Recent states, oldest first. `history[i]` is version `base + i`.
+    history: Vec<HashMap<String, Value>>,
+    base: usize,
+    /// The latest version each client has observed.
+    floors: HashMap<String, usize>,
+    rng: StdRng,
+}
+
+impl Default for SeqKv {
+    fn default() -> Self {
+        Self::with_rng(StdRng::from_entropy())
+    }
+}
+
+impl SeqKv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a store whose choice of stale reads is reproducible.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_rng(StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(rng: StdRng) -> Self {
+        Self {
+            history: vec![HashMap::new()],
+            base: 0,
+            floors: HashMap::new(),
+            rng,
+        }
+    }
+
+    fn latest(&self) -> usize {
+        self.base + self.history.len() - 1
+    }
+}
+
+impl Service for SeqKv {
+    fn handle(&mut self, req: &Message) -> Result<MessageBody, Error> {
+        let op = KvOp::parse(req)?;
+        let latest = self.latest();
+        let floor = self.floors.get(&req.src).copied().unwrap_or_default();
+
+        if let KvOp::Read { .. } = op {
+            let version = self.rng.gen_range(floor.max(self.base)..=latest);
+            self.floors.insert(req.src.clone(), version);
+            return op.apply(&mut self.history[version - self.base]);
+        }
+
+        // Writes see the latest state, even when they fail.
+        self.floors.insert(req.src.clone(), latest);
+        let mut data = self.history[latest - self.base].clone();
+        let res = op.apply(&mut data)?;
+
+        self.history.push(data);
+        self.floors.insert(req.src.clone(), latest + 1);
+        if self.history.len() > SEQ_KV_HISTORY {
+            self.history.remove(0);
+            self.base += 1;
+        }
+        Ok(res)
+    }
+}
+
+/// Last-writer-wins key-value store.
+///
+/// Each request is served by a random replica and replicas converge by keeping the latest
+/// write per key, so reads can be stale and concurrent compare-and-swaps can both succeed,
+/// losing one of the updates.
+#[derive(Debug)]
+pub struct LwwKv {
+    replicas: Vec<HashMap<String, (Value, u64)>>,
+    clock: u64,
+    rng: StdRng,
+}
+
+impl Default for LwwKv {
+    fn default() -> Self {
+        Self::with_rng(StdRng::from_entropy())
+    }
+}
+
+impl LwwKv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a store whose choice of replicas is reproducible.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_rng(StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(rng: StdRng) -> Self {
+        Self {
+            replicas: vec![HashMap::new(); LWW_KV_REPLICAS],
+            clock: 0,
+            rng,
+        }
+    }
+
+    /// Copy one random replica's newer writes into another.
+    fn anti_entropy(&mut self) {
+        let from = self.rng.gen_range(0..self.replicas.len());
+        let to = self.rng.gen_range(0..self.replicas.len());
+        if from == to {
+            return;
+        }
+        let source = self.replicas[from].clone();
+        for (key, (value, ts)) in source {
+            let entry = self.replicas[to].entry(key).or_insert((Value::Null, 0));
+            if ts > entry.1 {
+                *entry = (value, ts);
+            }
+        }
+    }
+}
+
+impl Service for LwwKv {
+    fn handle(&mut self, req: &Message) -> Result<MessageBody, Error> {
+        let op = KvOp::parse(req)?;
+        let replica = self.rng.gen_range(0..self.replicas.len());
+        let key = op.key().to_string();
+
+        let mut data: HashMap<String, Value> = self.replicas[replica]
+            .get(&key)
+            .map(|(value, _)| (key.clone(), value.clone()))
+            .into_iter()
+            .collect();
+        let write = !matches!(op, KvOp::Read { .. });
+        let res = op.apply(&mut data);
+
+        if write && res.is_ok() {
+            self.clock += 1;
+            let value = data.remove(&key).unwrap_or(Value::Null);
+            self.replicas[replica].insert(key, (value, self.clock));
+        }
+
+        self.anti_entropy();
+        res
+    }
+}
+
+/// Timestamp oracle handing out increasing timestamps, starting from 1.
+#[derive(Debug, Default)]
+pub struct LinTso {
+    ts: u64,
+}
+
+impl LinTso {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Service for LinTso {
+    fn handle(&mut self, req: &Message) -> Result<MessageBody, Error> {
+        match req.body.ty.as_str() {
+            "ts" => {
+                self.ts += 1;
+                Ok(MessageBody::new("ts_ok").with_field("ts", self.ts))
+            }
+            _ => Err(Error::not_supported()),
+        }
+    }
+}
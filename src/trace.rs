@@ -0,0 +1,147 @@
+use std::future::Future;
+
+use serde_json::Value;
+use tracing::field::{display, Empty};
+
+use crate::proto::{Message, MessageBody};
+
+/// Field carrying the trace id, as 32 hex digits.
+pub const TRACE_ID: &str = "trace_id";
+/// Field carrying the id of the sender's span, as 16 hex digits.
+pub const SPAN_ID: &str = "span_id";
+
+tokio::task_local! {
+    static CURRENT: SpanContext;
+}
+
+/// Identifies a span within a distributed trace.
+///
+/// Every request a node handles gets its own span, a child of the span that sent the request
+/// if the message carries one, and every RPC it sends gets a child of that. Requests from
+/// clients and RPCs sent outside a handler start new traces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpanContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub parent_span_id: Option<u64>,
+}
+
+impl SpanContext {
+    /// The first span of a new trace.
+    pub fn root() -> Self {
+        Self {
+            trace_id: rand::random::<u128>().max(1),
+            span_id: new_span_id(),
+            parent_span_id: None,
+        }
+    }
+
+    /// A new span in the same trace, with this one as its parent.
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id,
+            span_id: new_span_id(),
+            parent_span_id: Some(self.span_id),
+        }
+    }
+
+    /// The context of the request being handled by the current task, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|ctx| *ctx).ok()
+    }
+
+    /// The span for handling a message: a child of the span that sent it, or a new root.
+    pub fn for_message(body: &MessageBody) -> Self {
+        let trace_id = hex_field(body, TRACE_ID).and_then(|id| u128::from_str_radix(id, 16).ok());
+        let span_id = hex_field(body, SPAN_ID).and_then(|id| u64::from_str_radix(id, 16).ok());
+        match (trace_id, span_id) {
+            (Some(trace_id), Some(span_id)) => Self {
+                trace_id,
+                span_id: new_span_id(),
+                parent_span_id: Some(span_id),
+            },
+            _ => Self::root(),
+        }
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    pub fn span_id_hex(&self) -> String {
+        format!("{:016x}", self.span_id)
+    }
+
+    pub fn parent_span_id_hex(&self) -> Option<String> {
+        self.parent_span_id.map(|id| format!("{id:016x}"))
+    }
+
+    /// Tag an outgoing message as sent from this span.
+    pub(crate) fn inject(&self, body: &mut MessageBody) {
+        body.extra
+            .insert(TRACE_ID.to_string(), self.trace_id_hex().into());
+        body.extra
+            .insert(SPAN_ID.to_string(), self.span_id_hex().into());
+    }
+
+    /// Run `f` with this as the current context.
+    pub(crate) async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+}
+
+/// Remove the trace fields from a message, which differ on every run.
+pub(crate) fn strip(msg: &Message) -> Message {
+    let mut msg = msg.clone();
+    msg.body.extra.remove(TRACE_ID);
+    msg.body.extra.remove(SPAN_ID);
+    msg
+}
+
+/// Span covering the receipt of a message and, with a context, the handler it runs. The
+/// receiving node is the message's `dest`.
+pub(crate) fn request_span(req: &Message, ctx: Option<&SpanContext>) -> tracing::Span {
+    let span = tracing::info_span!(
+        "request",
+        node_id = %req.dst,
+        msg_id = req.body.msg_id,
+        ty = %req.ty(),
+        trace_id = Empty,
+        span_id = Empty,
+        parent_span_id = Empty,
+    );
+    if let Some(ctx) = ctx {
+        record(&span, ctx);
+    }
+    span
+}
+
+/// Span covering an RPC from send to reply.
+pub(crate) fn rpc_span(dst: &str, ty: &str, ctx: &SpanContext) -> tracing::Span {
+    let span = tracing::info_span!(
+        "rpc",
+        %dst,
+        %ty,
+        trace_id = Empty,
+        span_id = Empty,
+        parent_span_id = Empty,
+    );
+    record(&span, ctx);
+    span
+}
+
+fn record(span: &tracing::Span, ctx: &SpanContext) {
+    span.record("trace_id", display(ctx.trace_id_hex()));
+    span.record("span_id", display(ctx.span_id_hex()));
+    if let Some(parent) = ctx.parent_span_id_hex() {
+        span.record("parent_span_id", display(parent));
+    }
+}
+
+fn hex_field<'a>(body: &'a MessageBody, field: &str) -> Option<&'a str> {
+    body.extra.get(field).and_then(Value::as_str)
+}
+
+fn new_span_id() -> u64 {
+    rand::random::<u64>().max(1)
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::{error::ErrorKind, proto::MessageBody, Error, Node};
+
+/// Linearizable timestamp oracle.
+pub(crate) const LIN_TSO: &str = "lin-tso";
+
+/// Timestamps handed out locally from the last one fetched from the oracle.
+#[derive(Debug, Default)]
+struct Lease {
+    ts: u64,
+    /// Next sequence number within the lease, or `None` once the lease is used up.
+    next: Option<u64>,
+}
+
+/// Client for the `lin-tso` timestamp oracle.
+///
+/// Like [`Kv`](crate::kv::Kv), it can be cloned freely. Clones share the lease, so
+/// timestamps stay unique across every task handing them out.
+pub struct Tso<S> {
+    node: Node<S>,
+    bits: u32,
+    lease: Option<Arc<Mutex<Lease>>>,
+}
+
+impl<S> Clone for Tso<S> {
+    fn clone(&self) -> Self {
+        Self {
+            node: self.node.clone(),
+            bits: self.bits,
+            lease: self.lease.clone(),
+        }
+    }
+}
+
+impl<S> Tso<S> {
+    pub fn new(node: &Node<S>) -> Self {
+        Self {
+            node: node.clone(),
+            bits: 0,
+            lease: None,
+        }
+    }
+
+    /// Hand out `2^bits` timestamps for every one fetched from the oracle.
+    ///
+    /// Oracle timestamp `ts` becomes the lease `ts << bits | 0` through `ts << bits | 2^bits - 1`.
+    /// Leased timestamps are unique and increase for each client, and every timestamp from
+    /// a lease is below every timestamp from a later oracle response. They are not ordered
+    /// in real time across clients, though: another client may obtain a larger timestamp
+    /// before this one hands out the rest of its lease. All clients that compare timestamps
+    /// must use the same `bits`.
+    pub fn with_lease(mut self, bits: u32) -> Self {
+        assert!(bits < u64::BITS, "lease of {bits} bits is too large");
+        self.bits = bits;
+        self.lease = (bits > 0).then(|| Arc::new(Mutex::new(Lease::default())));
+        self
+    }
+}
+
+impl<S> Tso<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Get a new timestamp, from the current lease if there is one.
+    pub async fn ts(&self) -> Result<u64, Error> {
+        let Some(lease) = &self.lease else {
+            return self.fetch().await;
+        };
+
+        let mut lease = lease.lock().await;
+        let seq = match lease.next {
+            Some(seq) => seq,
+            None => {
+                lease.ts = self.fetch().await?;
+                0
+            }
+        };
+        lease.next = (seq + 1 < 1 << self.bits).then_some(seq + 1);
+
+        if lease.ts >> (u64::BITS - self.bits) != 0 {
+            return Err(Error::new(
+                ErrorKind::Crash,
+                format!("timestamp {} overflows a {}-bit lease", lease.ts, self.bits),
+            ));
+        }
+        Ok(lease.ts << self.bits | seq)
+    }
+
+    /// Get a timestamp directly from the oracle.
+    async fn fetch(&self) -> Result<u64, Error> {
+        let message = self
+            .node
+            .rpc(LIN_TSO.into(), MessageBody::new("ts"))
+            .await?;
+        message
+            .body
+            .extra
+            .get("ts")
+            .and_then(|ts| ts.as_u64())
+            .ok_or_else(|| Error::new(ErrorKind::Crash, "ts_ok without a timestamp"))
+    }
+}
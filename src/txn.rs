@@ -0,0 +1,238 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::future::try_join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    error::ErrorKind,
+    kv::{decode, encode, Kv},
+    proto::{Message, MessageBody},
+    Error,
+};
+
+/// Key holding the root map when none is configured.
+pub const DEFAULT_ROOT_KEY: &str = "root";
+
+/// A freshly written thunk may not be visible yet on stores weaker than lin-kv, so missing
+/// thunks are retried this many times before giving up.
+const THUNK_READ_ATTEMPTS: usize = 10;
+const THUNK_READ_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Maps each key, in its JSON encoding, to the id of the thunk holding its value.
+type Root = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpKind {
+    #[serde(rename = "r")]
+    Read,
+    #[serde(rename = "w")]
+    Write,
+    #[serde(rename = "append")]
+    Append,
+}
+
+/// A micro-operation, encoded as `[kind, key, value]`. Reads carry a null value until the
+/// transaction fills it in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Op(pub OpKind, pub Value, pub Value);
+
+struct KvTxnInner {
+    /// Thunks are immutable, so anything read or written once can be served locally.
+    thunks: Mutex<HashMap<String, Value>>,
+    next_id: AtomicU64,
+}
+
+/// Multi-key transactions over a key-value store.
+///
+/// Every value is written once to an immutable thunk under a fresh id, and a single root key
+/// maps keys to thunk ids. A transaction reads the root, writes thunks for the keys it
+/// changes, and commits by swapping the root with compare-and-swap. The root must live in a
+/// linearizable store; thunks may live anywhere.
+pub struct KvTxn<S> {
+    root: Kv<S>,
+    thunks: Kv<S>,
+    root_key: String,
+    inner: Arc<KvTxnInner>,
+}
+
+impl<S> Clone for KvTxn<S> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            thunks: self.thunks.clone(),
+            root_key: self.root_key.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S> KvTxn<S> {
+    /// Keep both the root and the thunks in `kv`, usually [`Kv::new_lin_kv`].
+    pub fn new(kv: Kv<S>) -> Self {
+        Self {
+            thunks: kv.clone(),
+            root: kv,
+            root_key: DEFAULT_ROOT_KEY.to_string(),
+            inner: Arc::new(KvTxnInner {
+                thunks: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Store thunks in a different key-value store.
+    pub fn with_thunk_store(mut self, kv: Kv<S>) -> Self {
+        self.thunks = kv;
+        self
+    }
+
+    /// Key holding the root map.
+    pub fn with_root_key(mut self, key: impl Into<String>) -> Self {
+        self.root_key = key.into();
+        self
+    }
+}
+
+impl<S> KvTxn<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Handle a `txn` request, replying with `txn_ok`.
+    pub async fn handle(&self, req: &Message) -> Result<MessageBody, Error> {
+        let ops: Vec<Op> = req
+            .body
+            .extra
+            .get("txn")
+            .cloned()
+            .and_then(|txn| serde_json::from_value(txn).ok())
+            .ok_or_else(Error::malformed_request)?;
+
+        let txn = self.transact(ops).await?;
+        Ok(MessageBody::new("txn_ok").with_field("txn", txn))
+    }
+
+    /// Run the operations against a snapshot of the store, returning them with reads filled
+    /// in.
+    ///
+    /// Fails with `txn_conflict` if another transaction committed first. Read-only
+    /// transactions never conflict.
+    pub async fn transact(&self, ops: Vec<Op>) -> Result<Vec<Op>, Error> {
+        let old = self.root.read(&self.root_key).await?;
+        let mut root: Root = old.clone().map(decode).transpose()?.unwrap_or_default();
+
+        let mut writes: BTreeMap<String, Value> = BTreeMap::new();
+        let mut results = Vec::with_capacity(ops.len());
+        for Op(kind, key, value) in ops {
+            let name = key.to_string();
+            let current = match (writes.get(&name), root.get(&name)) {
+                (Some(value), _) => Some(value.clone()),
+                (None, Some(id)) => Some(self.load(id).await?),
+                (None, None) => None,
+            };
+
+            match kind {
+                OpKind::Read => results.push(Op(kind, key, current.unwrap_or_default())),
+                OpKind::Write => {
+                    writes.insert(name, value.clone());
+                    results.push(Op(kind, key, value));
+                }
+                OpKind::Append => {
+                    let mut list = match current {
+                        Some(Value::Array(list)) => list,
+                        Some(Value::Null) | None => Vec::new(),
+                        Some(_) => {
+                            return Err(Error::new(
+                                ErrorKind::Abort,
+                                format!("cannot append to {name}: not a list"),
+                            ))
+                        }
+                    };
+                    list.push(value.clone());
+                    writes.insert(name, Value::Array(list));
+                    results.push(Op(kind, key, value));
+                }
+            }
+        }
+
+        if writes.is_empty() {
+            return Ok(results);
+        }
+
+        let node_id = self.root.node().id().await.clone();
+        let ids = try_join_all(writes.into_iter().map(|(name, value)| {
+            let id = format!(
+                "{node_id}-{}",
+                self.inner.next_id.fetch_add(1, Ordering::SeqCst)
+            );
+            async move {
+                self.store(&id, value).await?;
+                Ok::<_, Error>((name, id))
+            }
+        }))
+        .await?;
+        root.extend(ids);
+
+        let to = encode(&root)?;
+        let res = match &old {
+            Some(old) => {
+                self.root
+                    .compare_and_swap(&self.root_key, old, &to, false)
+                    .await
+            }
+            None => {
+                self.root
+                    .compare_and_swap(&self.root_key, &Value::Null, &to, true)
+                    .await
+            }
+        };
+
+        match res {
+            Ok(()) => Ok(results),
+            Err(err) if err.is_precondition_failed() || err.is_key_does_not_exist() => Err(
+                Error::new(ErrorKind::TxnConflict, "root changed during transaction"),
+            ),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn store(&self, id: &str, value: Value) -> Result<(), Error> {
+        self.thunks.write(id, &value).await?;
+        self.inner
+            .thunks
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), value);
+        Ok(())
+    }
+
+    async fn load(&self, id: &str) -> Result<Value, Error> {
+        if let Some(value) = self.inner.thunks.lock().unwrap().get(id) {
+            return Ok(value.clone());
+        }
+
+        for _ in 0..THUNK_READ_ATTEMPTS {
+            if let Some(value) = self.thunks.read(id).await? {
+                self.inner
+                    .thunks
+                    .lock()
+                    .unwrap()
+                    .insert(id.to_string(), value.clone());
+                return Ok(value);
+            }
+            tokio::time::sleep(THUNK_READ_BACKOFF).await;
+        }
+
+        Err(Error::new(
+            ErrorKind::Crash,
+            format!("thunk {id} not found"),
+        ))
+    }
+}
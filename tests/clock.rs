@@ -0,0 +1,121 @@
+use std::sync::{Arc, Mutex};
+
+use fly_dist_sys::{
+    clock::{timestamp, HybridClock, HybridTimestamp, LamportClock, VectorClock, VectorTimestamp},
+    proto::{Message, MessageBody},
+    sim::Sim,
+    Error, Node,
+};
+
+fn vts(entries: &[(&str, u64)]) -> VectorTimestamp {
+    VectorTimestamp(entries.iter().map(|(n, t)| (n.to_string(), *t)).collect())
+}
+
+#[test]
+fn vector_timestamps_order_by_causality() {
+    let a = vts(&[("n1", 1), ("n2", 2)]);
+    let b = vts(&[("n1", 2), ("n2", 2)]);
+    assert!(a.happened_before(&b));
+    assert!(!b.happened_before(&a));
+    assert!(a < b);
+
+    let c = vts(&[("n1", 2), ("n2", 1)]);
+    assert!(a.concurrent(&c));
+    assert_eq!(a.partial_cmp(&c), None);
+    assert_eq!(a, a.clone());
+}
+
+#[test]
+fn vector_timestamps_treat_missing_keys_as_zero() {
+    let a = vts(&[("n1", 1)]);
+    let b = vts(&[("n1", 1), ("n2", 1)]);
+    assert!(a.happened_before(&b));
+    assert_eq!(a, vts(&[("n1", 1), ("n2", 0)]));
+    assert!(vts(&[]).happened_before(&a));
+    assert!(a.concurrent(&vts(&[("n2", 1)])));
+
+    let mut merged = a.clone();
+    merged.merge(&vts(&[("n2", 3)]));
+    assert_eq!(merged, vts(&[("n1", 1), ("n2", 3)]));
+}
+
+#[test]
+fn lamport_observe_advances_past_both() {
+    let clock = LamportClock::new();
+    assert_eq!(clock.tick(), 1);
+    assert_eq!(clock.observe(10), 11);
+    assert_eq!(clock.observe(3), 12);
+    assert_eq!(clock.now(), 12);
+}
+
+#[test]
+fn hybrid_clock_is_monotonic_across_observe() {
+    let clock = HybridClock::new();
+    let first = clock.tick();
+
+    // A timestamp from a node whose wall clock runs far ahead.
+    let ahead = HybridTimestamp {
+        wall: first.wall + 60_000,
+        logical: 5,
+    };
+    let observed = clock.observe(ahead);
+    assert!(observed > ahead);
+    assert_eq!(observed.wall, ahead.wall);
+
+    // Local events stay ahead of it until the wall clock catches up.
+    let next = clock.tick();
+    assert!(next > observed);
+    assert!(clock.observe(first) > next);
+
+    let mut last = clock.now();
+    for _ in 0..100 {
+        let ts = clock.tick();
+        assert!(ts > last);
+        last = ts;
+    }
+}
+
+#[derive(Clone, Default)]
+struct State {
+    clock: Arc<VectorClock>,
+    /// The timestamp on the last `pong` this node received.
+    received: Arc<Mutex<Option<VectorTimestamp>>>,
+}
+
+async fn handle(node: Node<State>, req: Message) -> Result<MessageBody, Error> {
+    match req.ty() {
+        "ping" => {
+            node.rpc("n2".to_string(), MessageBody::new("pong")).await?;
+            Ok(MessageBody::new("ping_ok"))
+        }
+        "pong" => {
+            *node.state().received.lock().unwrap() = timestamp::<VectorClock>(&req.body);
+            Ok(MessageBody::new("pong_ok"))
+        }
+        _ => Err(Error::not_supported()),
+    }
+}
+
+#[tokio::test]
+async fn node_clocks_merge_on_receipt() {
+    let mut sim = Sim::new();
+    let mut states = Vec::new();
+    for node_id in ["n1", "n2"] {
+        let state = State::default();
+        let node = Node::with_state(state.clone()).with_clock(state.clock.clone());
+        sim.add_node(node_id, &node, handle);
+        states.push(state);
+    }
+    sim.init().await.unwrap();
+
+    sim.rpc("n1", MessageBody::new("ping")).await.unwrap();
+
+    let sent = states[1].received.lock().unwrap().clone().unwrap();
+    assert!(sent.get("n1") >= 1);
+    // n2 merged the stamp on receipt and ticked for the reply, which n1 merged in turn.
+    let n2 = states[1].clock.now();
+    assert!(sent.happened_before(&n2));
+    let n1 = states[0].clock.now();
+    assert!(n2.happened_before(&n1));
+    assert!(n1.get("n2") >= 1);
+}
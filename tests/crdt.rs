@@ -0,0 +1,189 @@
+use std::fmt::Debug;
+
+use fly_dist_sys::crdt::{Crdt, GCounter, GSet, LWWRegister, ORSet, PNCounter};
+use proptest::prelude::*;
+
+const NODES: [&str; 3] = ["n1", "n2", "n3"];
+
+/// A CRDT with a strategy for generating local updates.
+trait Model: Crdt + PartialEq + Debug {
+    type Op: Debug + Clone + 'static;
+
+    fn op() -> BoxedStrategy<Self::Op>;
+
+    fn apply(&mut self, node_id: &str, op: &Self::Op);
+}
+
+impl Model for GCounter {
+    type Op = u64;
+
+    fn op() -> BoxedStrategy<u64> {
+        (1..10u64).boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, n: &u64) {
+        self.increment(node_id, *n);
+    }
+}
+
+impl Model for PNCounter {
+    type Op = (bool, u64);
+
+    fn op() -> BoxedStrategy<(bool, u64)> {
+        (any::<bool>(), 1..10u64).boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, &(increment, n): &(bool, u64)) {
+        if increment {
+            self.increment(node_id, n);
+        } else {
+            self.decrement(node_id, n);
+        }
+    }
+}
+
+impl Model for GSet<u8> {
+    type Op = u8;
+
+    fn op() -> BoxedStrategy<u8> {
+        (0..8u8).boxed()
+    }
+
+    fn apply(&mut self, _node_id: &str, value: &u8) {
+        self.insert(*value);
+    }
+}
+
+impl Model for ORSet<u8> {
+    type Op = (bool, u8);
+
+    fn op() -> BoxedStrategy<(bool, u8)> {
+        (any::<bool>(), 0..8u8).boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, &(insert, value): &(bool, u8)) {
+        if insert {
+            self.insert(node_id, value);
+        } else {
+            self.remove(&value);
+        }
+    }
+}
+
+impl Model for LWWRegister<u8> {
+    type Op = (u64, u8);
+
+    fn op() -> BoxedStrategy<(u64, u8)> {
+        (0..16u64, any::<u8>()).boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, &(timestamp, value): &(u64, u8)) {
+        self.set(node_id, timestamp, value);
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Step<Op> {
+    Update(Op),
+    /// Merge another replica's state into this one.
+    Merge(usize),
+}
+
+/// Three replicas, one per node, built from a random history of local updates and merges.
+fn replicas<T: Model>() -> impl Strategy<Value = Vec<T>> {
+    let step = prop_oneof![
+        3 => T::op().prop_map(Step::Update),
+        1 => (0..NODES.len()).prop_map(Step::Merge),
+    ];
+    prop::collection::vec((0..NODES.len(), step), 0..40).prop_map(|history| {
+        let mut replicas = vec![T::default(); NODES.len()];
+        for (i, step) in history {
+            match step {
+                Step::Update(op) => replicas[i].apply(NODES[i], &op),
+                Step::Merge(j) => {
+                    let other = replicas[j].clone();
+                    replicas[i].merge(&other);
+                }
+            }
+        }
+        replicas
+    })
+}
+
+fn merged<T: Crdt>(x: &T, y: &T) -> T {
+    let mut x = x.clone();
+    x.merge(y);
+    x
+}
+
+fn check_laws<T: Model>(replicas: Vec<T>) -> Result<(), TestCaseError> {
+    let (x, y, z) = (&replicas[0], &replicas[1], &replicas[2]);
+
+    prop_assert_eq!(merged(x, y), merged(y, x), "commutative");
+    prop_assert_eq!(
+        merged(&merged(x, y), z),
+        merged(x, &merged(y, z)),
+        "associative"
+    );
+    prop_assert_eq!(merged(x, x), x.clone(), "idempotent");
+
+    let via_delta = match x.delta(y) {
+        Some(delta) => merged(y, &delta),
+        None => y.clone(),
+    };
+    prop_assert_eq!(via_delta, merged(y, x), "delta");
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn g_counter(replicas in replicas::<GCounter>()) {
+        check_laws(replicas)?;
+    }
+
+    #[test]
+    fn pn_counter(replicas in replicas::<PNCounter>()) {
+        check_laws(replicas)?;
+    }
+
+    #[test]
+    fn g_set(replicas in replicas::<GSet<u8>>()) {
+        check_laws(replicas)?;
+    }
+
+    #[test]
+    fn or_set(replicas in replicas::<ORSet<u8>>()) {
+        check_laws(replicas)?;
+    }
+
+    #[test]
+    fn lww_register(replicas in replicas::<LWWRegister<u8>>()) {
+        check_laws(replicas)?;
+    }
+
+    #[test]
+    fn or_set_add_wins(
+        replicas in replicas::<ORSet<u8>>(),
+        value in 0..8u8,
+    ) {
+        // n2 removes everything it has seen of `value` while n1 concurrently adds it again.
+        let mut adder = replicas[0].clone();
+        let mut remover = merged(&replicas[1], &adder);
+        remover.remove(&value);
+        adder.insert(NODES[0], value);
+
+        prop_assert!(merged(&adder, &remover).contains(&value));
+        prop_assert!(merged(&remover, &adder).contains(&value));
+    }
+}
+
+#[test]
+fn or_set_remove_observed() {
+    let mut a = ORSet::new();
+    a.insert("n1", 1);
+    let mut b = a.clone();
+    b.remove(&1);
+
+    assert!(!merged(&a, &b).contains(&1));
+    assert!(!merged(&b, &a).contains(&1));
+}
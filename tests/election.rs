@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use fly_dist_sys::{
+    election::{ElectionConfig, LeaderElection},
+    kv::Kv,
+    proto::Message,
+    sim::Sim,
+    Node,
+};
+
+const NODES: [&str; 3] = ["n1", "n2", "n3"];
+const LEASE: Duration = Duration::from_millis(300);
+
+async fn ignore(_node: Node<()>, _req: Message) {}
+
+/// Poll `f` until it returns `Some`, failing the test after a few seconds.
+async fn eventually<T>(what: &str, mut f: impl FnMut() -> Option<T>) -> T {
+    for _ in 0..100 {
+        if let Some(value) = f() {
+            return value;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("timed out waiting for {what}");
+}
+
+fn leaders(elections: &[LeaderElection<()>]) -> Vec<usize> {
+    (0..elections.len())
+        .filter(|&i| elections[i].is_leader())
+        .collect()
+}
+
+#[tokio::test]
+async fn leader_fails_over_after_lease() {
+    let mut sim = Sim::with_kv_services();
+    let config = ElectionConfig::new()
+        .with_lease_duration(LEASE)
+        .with_renew_interval(LEASE / 6);
+    let mut elections = Vec::new();
+    let mut tasks = Vec::new();
+    for node_id in NODES {
+        let node = Node::new();
+        let election = LeaderElection::new(Kv::new_lin_kv(&node), config.clone());
+        tasks.push(election.spawn(node.clone()));
+        sim.add_node(node_id, &node, ignore);
+        elections.push(election);
+    }
+    sim.init().await.unwrap();
+
+    let first = eventually("a leader", || leaders(&elections).first().copied()).await;
+    let term = elections[first].term().unwrap();
+    for _ in 0..10 {
+        assert_eq!(leaders(&elections), [first]);
+        tokio::time::sleep(LEASE / 5).await;
+    }
+
+    let follower = (first + 1) % NODES.len();
+    let mut leader_rx = elections[follower].subscribe();
+    assert_eq!(*leader_rx.borrow(), Some(NODES[first].to_string()));
+
+    tasks[first].abort();
+    let second = eventually("a new leader", || {
+        leaders(&elections).into_iter().find(|&i| i != first)
+    })
+    .await;
+    assert!(!elections[first].is_leader());
+    assert!(elections[second].term().unwrap() > term);
+
+    let changed = tokio::time::timeout(
+        LEASE * 4,
+        leader_rx.wait_for(|leader| leader.as_deref() == Some(NODES[second])),
+    )
+    .await;
+    assert!(changed.is_ok(), "subscriber missed the new leader");
+}
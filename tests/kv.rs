@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use fly_dist_sys::{
+    kv::{CacheStats, Kv},
+    proto::{Message, MessageBody},
+    sim::Sim,
+    Node,
+};
+use serde_json::json;
+
+const TTL: Duration = Duration::from_secs(60);
+
+async fn ignore(_node: Node<()>, _req: Message) {}
+
+/// A node on a network with the key-value services, for driving a [`Kv`] from the test.
+async fn node() -> (Sim, Node<()>) {
+    let mut sim = Sim::with_kv_services();
+    let node = Node::new();
+    sim.add_node("n1", &node, ignore);
+    sim.init().await.unwrap();
+    (sim, node)
+}
+
+fn write(key: &str, value: u64) -> MessageBody {
+    MessageBody::new("write")
+        .with_field("key", key)
+        .with_field("value", value)
+}
+
+#[tokio::test]
+async fn cache_hits_and_misses() {
+    let (_sim, node) = node().await;
+    let kv = Kv::new_seq_kv(&node).with_cache(TTL);
+
+    assert_eq!(kv.read("x").await.unwrap(), None);
+    assert_eq!(kv.read("x").await.unwrap(), None);
+    kv.write("y", 1).await.unwrap();
+    assert_eq!(kv.read("y").await.unwrap(), Some(json!(1)));
+
+    let stats = kv.cache_stats().unwrap();
+    assert_eq!(stats, CacheStats { hits: 2, misses: 1 });
+}
+
+#[tokio::test]
+async fn cache_writes_through_and_evicts_on_failed_cas() {
+    let (sim, node) = node().await;
+    let kv = Kv::new_seq_kv(&node).with_cache(TTL);
+
+    kv.write("x", 1).await.unwrap();
+    // Another client's write is invisible until the entry goes.
+    sim.rpc("seq-kv", write("x", 2)).await.unwrap();
+    assert_eq!(kv.read("x").await.unwrap(), Some(json!(1)));
+
+    kv.compare_and_swap("x", &json!(2), &json!(3), false)
+        .await
+        .unwrap();
+    assert_eq!(kv.read("x").await.unwrap(), Some(json!(3)));
+
+    sim.rpc("seq-kv", write("x", 4)).await.unwrap();
+    let err = kv
+        .compare_and_swap("x", &json!(3), &json!(5), false)
+        .await
+        .unwrap_err();
+    assert!(err.is_precondition_failed());
+    assert_eq!(kv.read("x").await.unwrap(), Some(json!(4)));
+
+    let stats = kv.cache_stats().unwrap();
+    assert_eq!(stats, CacheStats { hits: 2, misses: 1 });
+}
+
+#[tokio::test]
+async fn cache_is_ignored_for_lin_kv() {
+    let (sim, node) = node().await;
+    let kv = Kv::new_lin_kv(&node).with_cache(TTL);
+    assert_eq!(kv.cache_stats(), None);
+
+    kv.write("x", 1).await.unwrap();
+    sim.rpc("lin-kv", write("x", 2)).await.unwrap();
+    assert_eq!(kv.read("x").await.unwrap(), Some(json!(2)));
+}
+
+#[tokio::test]
+async fn cache_keeps_writes_over_racing_reads() {
+    let (_sim, node) = node().await;
+    let kv = Kv::new_seq_kv(&node).with_cache(TTL);
+
+    // Whichever way the read and the write interleave, the write completed last, so the
+    // node must not go back to an older value afterwards.
+    for i in 0..20 {
+        let (_, written) = tokio::join!(kv.read("x"), kv.write("x", i));
+        written.unwrap();
+        assert_eq!(kv.read("x").await.unwrap(), Some(json!(i)));
+    }
+}
@@ -0,0 +1,134 @@
+use std::{future::Future, time::Duration};
+
+use fly_dist_sys::{
+    crdt::GCounter,
+    gossip::{Gossip, GossipConfig, GOSSIP},
+    membership::{MemberState, Membership, MembershipChange, MembershipConfig, PING, PING_REQ},
+    proto::{Message, MessageBody},
+    sim::Sim,
+    Error, Node,
+};
+use tokio::sync::broadcast;
+
+const NODES: [&str; 3] = ["n1", "n2", "n3"];
+
+#[derive(Clone)]
+struct State {
+    membership: Membership,
+    counter: Gossip<GCounter>,
+}
+
+impl State {
+    fn new() -> Self {
+        let membership = Membership::new(
+            MembershipConfig::new()
+                .with_probe_interval(Duration::from_millis(20))
+                .with_probe_timeout(Duration::from_millis(20))
+                .with_suspicion_timeout(Duration::from_millis(100)),
+        );
+        let gossip = GossipConfig::new()
+            .with_interval(Duration::from_millis(20))
+            .with_timeout(Duration::from_millis(100))
+            .with_membership(membership.clone());
+        Self {
+            membership,
+            counter: Gossip::new(gossip),
+        }
+    }
+}
+
+async fn handle(node: Node<State>, req: Message) -> Result<MessageBody, Error> {
+    match req.ty() {
+        PING | PING_REQ => node.state().membership.handle(&node, &req).await,
+        GOSSIP => node.state().counter.handle(&req),
+        "add" => {
+            let node_id = node.id().await.clone();
+            node.state()
+                .counter
+                .update(|counter| counter.increment(&node_id, 1));
+            Ok(MessageBody::new("add_ok"))
+        }
+        "read" => {
+            let value = node.state().counter.value();
+            Ok(MessageBody::new("read_ok").with_field("value", value))
+        }
+        _ => Err(Error::not_supported()),
+    }
+}
+
+/// Run `f`, failing the test if it takes more than a few seconds.
+async fn timeout<T>(f: impl Future<Output = T>, what: &str) -> T {
+    tokio::time::timeout(Duration::from_secs(5), f)
+        .await
+        .unwrap_or_else(|_| panic!("timed out waiting for {what}"))
+}
+
+/// The next change `rx` reports for `node_id`.
+async fn next_change(rx: &mut broadcast::Receiver<MembershipChange>, node_id: &str) -> MemberState {
+    let change = async {
+        loop {
+            let change = rx.recv().await.unwrap();
+            if change.node_id == node_id {
+                return change.state;
+            }
+        }
+    };
+    timeout(change, "a membership change").await
+}
+
+async fn read(sim: &Sim, node_id: &str) -> u64 {
+    let res = sim.rpc(node_id, MessageBody::new("read")).await.unwrap();
+    res.body.extra["value"].as_u64().unwrap()
+}
+
+/// Wait until `node_id` has counted the one `add`.
+async fn caught_up(sim: &Sim, node_id: &str) {
+    while read(sim, node_id).await < 1 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn unreachable_peer_dies_and_recovers() {
+    let mut sim = Sim::new();
+    let mut states = Vec::new();
+    for node_id in NODES {
+        let state = State::new();
+        let node = Node::with_state(state.clone());
+        state.membership.spawn(node.clone());
+        state.counter.spawn(node.clone());
+        sim.add_node(node_id, &node, handle);
+        states.push(state);
+    }
+    let mut changes = states[0].membership.subscribe();
+    sim.init().await.unwrap();
+    assert_eq!(states[0].membership.state("n3"), Some(MemberState::Alive));
+
+    // n3 still hears from its peers but nothing it sends reaches them.
+    sim.disconnect("n3", "n1");
+    sim.disconnect("n3", "n2");
+    assert_eq!(next_change(&mut changes, "n3").await, MemberState::Suspect);
+    assert_eq!(next_change(&mut changes, "n3").await, MemberState::Dead);
+    assert_eq!(states[0].membership.alive_peers(), ["n2"]);
+    assert!(!states[0].membership.is_alive("n3"));
+
+    // Once n2 has also given up on n3, neither of them gossips to it.
+    let n2_gave_up = async {
+        while states[1].membership.state("n3") != Some(MemberState::Dead) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(n2_gave_up, "n2 to declare n3 dead").await;
+    sim.rpc("n1", MessageBody::new("add")).await.unwrap();
+    timeout(caught_up(&sim, "n2"), "n2 to see the update").await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(read(&sim, "n3").await, 0);
+
+    sim.heal();
+    assert_eq!(next_change(&mut changes, "n3").await, MemberState::Alive);
+    timeout(caught_up(&sim, "n3"), "n3 to see the update").await;
+    assert_eq!(
+        states[0].membership.members().get("n3"),
+        Some(&MemberState::Alive)
+    );
+}
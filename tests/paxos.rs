@@ -0,0 +1,55 @@
+use fly_dist_sys::{
+    paxos::Paxos,
+    proto::{Message, MessageBody},
+    sim::Sim,
+    Error, Node,
+};
+use futures::future::try_join_all;
+use serde_json::Value;
+
+const NODES: [&str; 3] = ["n1", "n2", "n3"];
+
+async fn handle(node: Node<Paxos<String>>, req: Message) -> Result<Option<MessageBody>, Error> {
+    let paxos = node.state();
+    match req.ty() {
+        "propose" => {
+            let value = req.body.extra["value"].as_str().unwrap().to_string();
+            let decided = paxos.propose(&node, 0, value).await?;
+            Ok(Some(
+                MessageBody::new("propose_ok").with_field("value", decided),
+            ))
+        }
+        _ => paxos.handle(&req),
+    }
+}
+
+#[tokio::test]
+async fn duelling_proposers_decide_once() {
+    let mut sim = Sim::new();
+    let mut instances = Vec::new();
+    for node_id in NODES {
+        let paxos = Paxos::default();
+        sim.add_node(node_id, &Node::with_state(paxos.clone()), handle);
+        instances.push(paxos);
+    }
+    sim.init().await.unwrap();
+
+    // Every node proposes its own value at once.
+    let replies = try_join_all(NODES.map(|node_id| {
+        let propose = MessageBody::new("propose").with_field("value", node_id);
+        sim.rpc(node_id, propose)
+    }))
+    .await
+    .unwrap();
+
+    let decided: Vec<&Value> = replies.iter().map(|res| &res.body.extra["value"]).collect();
+    assert!(
+        decided.iter().all(|value| *value == decided[0]),
+        "{decided:?}"
+    );
+    assert!(NODES.iter().any(|node_id| decided[0] == node_id));
+    for paxos in &instances {
+        let learned = paxos.learner().wait(0).await;
+        assert_eq!(decided[0], &learned);
+    }
+}
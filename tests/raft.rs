@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use fly_dist_sys::{
+    proto::{Message, MessageBody},
+    raft::{Raft, RaftConfig, StateMachine},
+    sim::Sim,
+    Error, Node,
+};
+use serde_json::{json, Value};
+
+const NODES: [&str; 3] = ["n1", "n2", "n3"];
+
+/// A single replicated value.
+#[derive(Debug, Default)]
+struct Register {
+    value: Option<Value>,
+}
+
+impl StateMachine for Register {
+    fn apply(&mut self, req: &MessageBody) -> Result<MessageBody, Error> {
+        match req.ty.as_str() {
+            "read" => {
+                let value = self.value.clone().ok_or_else(Error::key_does_not_exist)?;
+                Ok(MessageBody::new("read_ok").with_field("value", value))
+            }
+            "write" => {
+                self.value = req.extra.get("value").cloned();
+                Ok(MessageBody::new("write_ok"))
+            }
+            _ => Err(Error::not_supported()),
+        }
+    }
+}
+
+async fn handle(node: Node<Raft<Register>>, req: Message) -> Result<MessageBody, Error> {
+    node.state().handle(&node, req).await
+}
+
+/// Poll `f` until it returns `Some`, failing the test after a few seconds.
+async fn eventually<T>(what: &str, mut f: impl FnMut() -> Option<T>) -> T {
+    for _ in 0..100 {
+        if let Some(value) = f() {
+            return value;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("timed out waiting for {what}");
+}
+
+#[tokio::test]
+async fn elects_a_leader_and_replicates() {
+    let mut sim = Sim::new();
+    let mut rafts = Vec::new();
+    for node_id in NODES {
+        let raft = Raft::new(Register::default(), RaftConfig::default());
+        let node = Node::with_state(raft.clone());
+        raft.spawn(node.clone());
+        sim.add_node(node_id, &node, handle);
+        rafts.push(raft);
+    }
+    sim.init().await.unwrap();
+
+    let leader = eventually("a leader", || {
+        let leaders: Vec<_> = rafts.iter().filter(|raft| raft.is_leader()).collect();
+        let leader = leaders.first()?.leader()?;
+        let agreed = leaders.len() == 1
+            && rafts
+                .iter()
+                .all(|raft| raft.leader().as_ref() == Some(&leader));
+        agreed.then_some(leader)
+    })
+    .await;
+
+    // Followers forward client requests to the leader.
+    let follower = NODES.into_iter().find(|n| *n != leader).unwrap();
+    let write = MessageBody::new("write").with_field("value", 42);
+    sim.rpc(follower, write).await.unwrap();
+    let res = sim.rpc(&leader, MessageBody::new("read")).await.unwrap();
+    assert_eq!(res.body.extra["value"], json!(42));
+
+    eventually("every node to apply the write", || {
+        rafts
+            .iter()
+            .all(|raft| raft.read(|register| register.value == Some(json!(42))))
+            .then_some(())
+    })
+    .await;
+}
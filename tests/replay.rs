@@ -0,0 +1,71 @@
+use fly_dist_sys::{
+    proto::{Message, MessageBody},
+    record::{Direction, Record},
+    replay::Replay,
+    Error, Node,
+};
+use serde_json::json;
+
+fn record(direction: Direction, mono: u64, msg: serde_json::Value) -> Record {
+    Record {
+        direction,
+        wall: mono,
+        mono,
+        msg: serde_json::from_value(msg).unwrap(),
+    }
+}
+
+/// Answers `fetch` with a value read from `n2`.
+async fn fetch(node: Node<()>, req: Message) -> Result<MessageBody, Error> {
+    let res = node.rpc("n2".to_string(), MessageBody::new("get")).await?;
+    let value = res.body.extra.get("value").cloned();
+    Ok(MessageBody::new("fetch_ok")
+        .with_field("value", value)
+        .with_field("for", req.src))
+}
+
+#[tokio::test]
+async fn replays_replies_to_rpcs() {
+    let records = vec![
+        record(
+            Direction::In,
+            0,
+            json!({"src": "c0", "dest": "n1", "body": {
+                "type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"],
+            }}),
+        ),
+        record(
+            Direction::Out,
+            1,
+            json!({"src": "n1", "dest": "c0", "body": {"type": "init_ok", "in_reply_to": 1}}),
+        ),
+        record(
+            Direction::In,
+            2,
+            json!({"src": "c1", "dest": "n1", "body": {"type": "fetch", "msg_id": 1}}),
+        ),
+        record(
+            Direction::Out,
+            3,
+            json!({"src": "n1", "dest": "n2", "body": {"type": "get", "msg_id": 1}}),
+        ),
+        record(
+            Direction::In,
+            4,
+            json!({"src": "n2", "dest": "n1", "body": {
+                "type": "get_ok", "in_reply_to": 1, "value": 42,
+            }}),
+        ),
+        record(
+            Direction::Out,
+            5,
+            json!({"src": "n1", "dest": "c1", "body": {
+                "type": "fetch_ok", "in_reply_to": 1, "value": 42, "for": "c1",
+            }}),
+        ),
+    ];
+
+    let node = Node::new();
+    let report = Replay::new(records).run(&node, fetch).await;
+    assert!(report.is_ok(), "{report}");
+}
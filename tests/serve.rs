@@ -0,0 +1,188 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use fly_dist_sys::{
+    error::ErrorKind,
+    proto::{Message, MessageBody},
+    serve::{Dispatch, Overload, ServeConfig, ServeStats},
+    sim::Sim,
+    Error, Node,
+};
+use futures::future::try_join_all;
+use tokio::sync::Semaphore;
+
+/// Requests each client sends in a burst.
+const BURST: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Event {
+    Start,
+    End,
+}
+
+#[derive(Clone)]
+struct State {
+    /// Every `work` request as it starts and ends, by source and sequence number.
+    log: Arc<Mutex<Vec<(String, u64, Event)>>>,
+    /// Permits for `block` requests to finish.
+    release: Arc<Semaphore>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            log: Arc::default(),
+            release: Arc::new(Semaphore::new(0)),
+        }
+    }
+}
+
+async fn handle(node: Node<State>, req: Message) -> Result<MessageBody, Error> {
+    match req.ty() {
+        // Send a burst of `work` requests to `n1`, in order.
+        "burst" => {
+            let requests = (0..BURST).map(|seq| {
+                let work = MessageBody::new("work").with_field("seq", seq);
+                node.rpc("n1".to_string(), work)
+            });
+            try_join_all(requests).await?;
+            Ok(MessageBody::new("burst_ok"))
+        }
+        "work" => {
+            let seq = req.body.extra["seq"].as_u64().unwrap();
+            let log = &node.state().log;
+            log.lock()
+                .unwrap()
+                .push((req.src.clone(), seq, Event::Start));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            log.lock().unwrap().push((req.src, seq, Event::End));
+            Ok(MessageBody::new("work_ok"))
+        }
+        "block" => {
+            node.state().release.acquire().await.unwrap().forget();
+            Ok(MessageBody::new("block_ok"))
+        }
+        _ => Err(Error::not_supported()),
+    }
+}
+
+#[tokio::test]
+async fn per_source_dispatch_orders_each_source() {
+    let mut sim = Sim::new();
+    let server = Node::<State>::default();
+    let config = ServeConfig::new().with_dispatch(Dispatch::PerSource);
+    sim.add_node_with("n1", &server, config, handle);
+    for node_id in ["n2", "n3"] {
+        sim.add_node(node_id, &Node::<State>::default(), handle);
+    }
+    sim.init().await.unwrap();
+
+    let bursts = ["n2", "n3"].map(|node_id| sim.rpc(node_id, MessageBody::new("burst")));
+    try_join_all(bursts).await.unwrap();
+
+    let log = server.state().log.lock().unwrap().clone();
+    for src in ["n2", "n3"] {
+        let events: Vec<_> = log
+            .iter()
+            .filter(|(from, _, _)| from == src)
+            .map(|(_, seq, event)| (*seq, *event))
+            .collect();
+        let expected: Vec<_> = (0..BURST)
+            .flat_map(|seq| [(seq, Event::Start), (seq, Event::End)])
+            .collect();
+        assert_eq!(events, expected, "requests from {src}");
+    }
+
+    // The two sources were handled side by side: both started before either finished.
+    let first_end = log.iter().position(|(_, _, e)| *e == Event::End).unwrap();
+    let started: Vec<_> = log[..first_end].iter().map(|(src, _, _)| src).collect();
+    assert_eq!(started.len(), 2, "{log:?}");
+}
+
+/// Poll `f` until it returns `true`, failing the test after a few seconds.
+async fn eventually(what: &str, mut f: impl FnMut() -> bool) {
+    for _ in 0..100 {
+        if f() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("timed out waiting for {what}");
+}
+
+async fn saturated(config: ServeConfig) -> (Sim, Node<State>) {
+    let mut sim = Sim::new();
+    let node = Node::<State>::default();
+    sim.add_node_with("n1", &node, config, handle);
+    sim.init().await.unwrap();
+    (sim, node)
+}
+
+#[tokio::test]
+async fn saturated_node_rejects() {
+    let config = ServeConfig::new().with_max_in_flight(1).with_max_queued(0);
+    let (sim, node) = saturated(config).await;
+
+    let (res, ()) = tokio::join!(sim.rpc("n1", MessageBody::new("block")), async {
+        eventually("the handler to start", || node.serve_stats().in_flight == 1).await;
+        let err = sim.rpc("n1", MessageBody::new("block")).await.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::TemporarlilyUnavailable);
+        assert_eq!(err.kind as u8, 11);
+        node.state().release.add_permits(1);
+    });
+    res.unwrap();
+    assert_eq!(node.serve_stats().rejected, 1);
+}
+
+#[tokio::test]
+async fn saturated_node_drops() {
+    let config = ServeConfig::new()
+        .with_max_in_flight(1)
+        .with_max_queued(0)
+        .with_overload(Overload::Drop);
+    let (sim, node) = saturated(config).await;
+
+    let (res, ()) = tokio::join!(sim.rpc("n1", MessageBody::new("block")), async {
+        eventually("the handler to start", || node.serve_stats().in_flight == 1).await;
+        let dropped = tokio::time::timeout(
+            Duration::from_millis(200),
+            sim.rpc("n1", MessageBody::new("block")),
+        )
+        .await;
+        assert!(dropped.is_err(), "got a reply: {dropped:?}");
+        node.state().release.add_permits(1);
+    });
+    res.unwrap();
+    assert_eq!(node.serve_stats().rejected, 1);
+}
+
+#[tokio::test]
+async fn serve_stats_reports_queue_depth() {
+    let config = ServeConfig::new().with_max_in_flight(1).with_max_queued(2);
+    let (sim, node) = saturated(config).await;
+
+    let blocked = (0..3).map(|_| sim.rpc("n1", MessageBody::new("block")));
+    let (res, ()) = tokio::join!(try_join_all(blocked), async {
+        let expected = ServeStats {
+            in_flight: 1,
+            queued: 2,
+            rejected: 0,
+        };
+        eventually("the queue to fill", || node.serve_stats() == expected).await;
+        let err = sim.rpc("n1", MessageBody::new("block")).await.unwrap_err();
+        assert!(err.is_temporarily_unavailable());
+        node.state().release.add_permits(3);
+    });
+    res.unwrap();
+    eventually("the queue to drain", || {
+        node.serve_stats()
+            == ServeStats {
+                in_flight: 0,
+                queued: 0,
+                rejected: 1,
+            }
+    })
+    .await;
+}
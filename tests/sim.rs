@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use fly_dist_sys::{
+    crdt::GCounter,
+    error::ErrorKind,
+    gossip::{Gossip, GOSSIP},
+    proto::{Message, MessageBody},
+    sim::{LwwKv, SeqKv, Sim},
+    Error, Node,
+};
+use serde_json::{json, Value};
+
+fn read(key: &str) -> MessageBody {
+    MessageBody::new("read").with_field("key", key)
+}
+
+fn write(key: &str, value: u64) -> MessageBody {
+    MessageBody::new("write")
+        .with_field("key", key)
+        .with_field("value", value)
+}
+
+fn cas(key: &str, from: u64, to: u64) -> MessageBody {
+    MessageBody::new("cas")
+        .with_field("key", key)
+        .with_field("from", from)
+        .with_field("to", to)
+}
+
+fn value(res: &Message) -> Value {
+    res.body.extra["value"].clone()
+}
+
+/// Forwards every request to the store named by the node's state, so each node is a
+/// separate client of it.
+async fn proxy(node: Node<&'static str>, req: Message) -> Result<MessageBody, Error> {
+    let service = node.state().to_string();
+    let body = MessageBody {
+        msg_id: 0,
+        ..req.body
+    };
+    let res = node.rpc(service, body).await?;
+    Ok(MessageBody {
+        msg_id: 0,
+        in_reply_to: 0,
+        ..res.body
+    })
+}
+
+async fn proxies(mut sim: Sim, service: &'static str) -> Sim {
+    for node_id in ["n1", "n2"] {
+        sim.add_node(node_id, &Node::with_state(service), proxy);
+    }
+    sim.init().await.unwrap();
+    sim
+}
+
+#[tokio::test]
+async fn lin_kv_cas() {
+    let sim = Sim::with_kv_services();
+
+    let err = sim.rpc("lin-kv", cas("x", 0, 1)).await.unwrap_err();
+    assert_eq!(err.kind, ErrorKind::KeyDoesNotExist);
+
+    sim.rpc("lin-kv", write("x", 0)).await.unwrap();
+    let res = sim.rpc("lin-kv", cas("x", 0, 1)).await.unwrap();
+    assert_eq!(res.ty(), "cas_ok");
+
+    let err = sim.rpc("lin-kv", cas("x", 0, 2)).await.unwrap_err();
+    assert_eq!(err.kind, ErrorKind::PreconditionFailed);
+
+    let res = sim.rpc("lin-kv", read("x")).await.unwrap();
+    assert_eq!(value(&res), json!(1));
+}
+
+#[tokio::test]
+async fn seq_kv_reads_are_stale_but_monotonic() {
+    let mut sim = Sim::new();
+    sim.add_service("seq-kv", SeqKv::with_seed(7));
+    let sim = proxies(sim, "seq-kv").await;
+
+    for i in 0..20 {
+        sim.rpc("n1", write("x", i)).await.unwrap();
+    }
+    // The writer always sees its own writes.
+    let res = sim.rpc("n1", read("x")).await.unwrap();
+    assert_eq!(value(&res), json!(19));
+
+    let mut last = -1;
+    let mut stale = false;
+    for _ in 0..50 {
+        let seen = match sim.rpc("n2", read("x")).await {
+            Ok(res) => value(&res).as_i64().unwrap(),
+            Err(err) if err.kind == ErrorKind::KeyDoesNotExist => -1,
+            Err(err) => panic!("{err}"),
+        };
+        assert!(seen >= last, "read {seen} after {last}");
+        stale |= seen < 19;
+        last = seen;
+    }
+    assert!(stale, "no stale reads");
+}
+
+#[tokio::test]
+async fn lww_kv_loses_concurrent_updates() {
+    let mut sim = Sim::new();
+    sim.add_service("lww-kv", LwwKv::with_seed(7));
+    let sim = proxies(sim, "lww-kv").await;
+
+    // Both clients swap from the same value; a linearizable store would reject one of them.
+    let mut lost = 0;
+    for i in 0..20 {
+        let key = format!("k{i}");
+        sim.rpc("n1", write(&key, 0)).await.unwrap();
+        let first = sim.rpc("n1", cas(&key, 0, 1)).await;
+        let second = sim.rpc("n2", cas(&key, 0, 2)).await;
+        if first.is_ok() && second.is_ok() {
+            lost += 1;
+        }
+    }
+    assert!(lost > 0, "no updates lost");
+}
+
+#[derive(Clone, Default)]
+struct CounterState {
+    counter: Gossip<GCounter>,
+}
+
+async fn g_counter(node: Node<CounterState>, req: Message) -> Result<MessageBody, Error> {
+    match req.ty() {
+        "add" => {
+            let delta = req.body.extra["delta"].as_u64().unwrap();
+            let node_id = node.id().await.clone();
+            node.state()
+                .counter
+                .update(|counter| counter.increment(&node_id, delta));
+            Ok(MessageBody::new("add_ok"))
+        }
+        "read" => {
+            let value = node.state().counter.value();
+            Ok(MessageBody::new("read_ok").with_field("value", value))
+        }
+        GOSSIP => node.state().counter.handle(&req),
+        _ => Err(Error::not_supported()),
+    }
+}
+
+#[tokio::test]
+async fn g_counter_converges() {
+    let mut sim = Sim::new();
+    for node_id in ["n1", "n2", "n3"] {
+        let node = Node::<CounterState>::default();
+        node.state().counter.spawn(node.clone());
+        sim.add_node(node_id, &node, g_counter);
+    }
+    sim.init().await.unwrap();
+
+    for (node_id, delta) in [("n1", 1), ("n2", 2), ("n3", 3), ("n1", 4)] {
+        let add = MessageBody::new("add").with_field("delta", delta);
+        sim.rpc(node_id, add).await.unwrap();
+    }
+
+    for node_id in ["n1", "n2", "n3"] {
+        let mut attempts = 0;
+        loop {
+            let res = sim.rpc(node_id, MessageBody::new("read")).await.unwrap();
+            if value(&res) == json!(10) {
+                break;
+            }
+            attempts += 1;
+            assert!(attempts < 50, "{node_id} read {}", value(&res));
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
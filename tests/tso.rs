@@ -0,0 +1,37 @@
+use fly_dist_sys::{
+    proto::{Message, MessageBody},
+    sim::Sim,
+    tso::Tso,
+    Node,
+};
+
+async fn ignore(_node: Node<()>, _req: Message) {}
+
+#[tokio::test]
+async fn leases_split_oracle_timestamps() {
+    let mut sim = Sim::with_kv_services();
+    let node = Node::new();
+    sim.add_node("n1", &node, ignore);
+    sim.init().await.unwrap();
+
+    // Oracle timestamp 1 becomes the lease 1 << 2 | 0..4.
+    let tso = Tso::new(&node).with_lease(2);
+    let mut leased = Vec::new();
+    for _ in 0..4 {
+        leased.push(tso.ts().await.unwrap());
+    }
+    assert_eq!(leased, [4, 5, 6, 7]);
+
+    // Every timestamp in the lease is below anything based on a later oracle response.
+    let res = sim.rpc("lin-tso", MessageBody::new("ts")).await.unwrap();
+    let next = res.body.extra["ts"].as_u64().unwrap();
+    assert_eq!(next, 2);
+    assert!(leased.iter().all(|&ts| ts < next << 2));
+
+    // The lease is used up, so the next timestamp comes from a new one, which clones share.
+    assert_eq!(tso.ts().await.unwrap(), 3 << 2);
+    assert_eq!(tso.clone().ts().await.unwrap(), 3 << 2 | 1);
+
+    // Without a lease, every timestamp comes straight from the oracle.
+    assert_eq!(Tso::new(&node).ts().await.unwrap(), 4);
+}
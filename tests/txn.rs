@@ -0,0 +1,65 @@
+use std::sync::{Arc, OnceLock};
+
+use fly_dist_sys::{
+    error::ErrorKind,
+    kv::Kv,
+    proto::{Message, MessageBody},
+    sim::Sim,
+    txn::{KvTxn, Op, OpKind},
+    Error, Node,
+};
+use futures::future::join_all;
+use serde_json::{json, Value};
+
+/// Node state holding a transaction client, created from the node on first use.
+#[derive(Clone, Default)]
+struct State(Arc<OnceLock<KvTxn<State>>>);
+
+async fn handle(node: Node<State>, req: Message) -> Result<MessageBody, Error> {
+    let txn = node
+        .state()
+        .0
+        .get_or_init(|| KvTxn::new(Kv::new_lin_kv(&node)));
+    txn.handle(&req).await
+}
+
+fn txn(ops: Vec<Op>) -> MessageBody {
+    MessageBody::new("txn").with_field("txn", ops)
+}
+
+/// Run a transaction on `node_id`, retrying it on conflict. Returns the number of conflicts.
+async fn transact_with_retry(sim: &Sim, node_id: &str, ops: Vec<Op>) -> usize {
+    let mut conflicts = 0;
+    loop {
+        match sim.rpc(node_id, txn(ops.clone())).await {
+            Ok(_) => return conflicts,
+            Err(err) if err.kind == ErrorKind::TxnConflict => conflicts += 1,
+            Err(err) => panic!("{err}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn conflicting_transactions_retry() {
+    let mut sim = Sim::with_kv_services();
+    for node_id in ["n1", "n2"] {
+        sim.add_node(node_id, &Node::<State>::default(), handle);
+    }
+    sim.init().await.unwrap();
+
+    // Every transaction reads the same root, so all but one commit conflict on each round.
+    let appends = (0..10u64).map(|i| {
+        let node_id = if i % 2 == 0 { "n1" } else { "n2" };
+        let ops = vec![Op(OpKind::Append, json!("x"), json!(i))];
+        transact_with_retry(&sim, node_id, ops)
+    });
+    let conflicts: usize = join_all(appends).await.into_iter().sum();
+    assert!(conflicts > 0, "no transaction conflicted");
+
+    let read = vec![Op(OpKind::Read, json!("x"), Value::Null)];
+    let res = sim.rpc("n1", txn(read)).await.unwrap();
+    let ops: Vec<Op> = serde_json::from_value(res.body.extra["txn"].clone()).unwrap();
+    let mut list: Vec<u64> = serde_json::from_value(ops[0].2.clone()).unwrap();
+    list.sort();
+    assert_eq!(list, (0..10).collect::<Vec<_>>());
+}